@@ -0,0 +1,118 @@
+//! Access to the trap CSRs of the privilege mode the emulator runs in.
+
+macro_rules! read_csr {
+    ($csr:literal) => {{
+        let value: usize;
+        // SAFETY: reading a trap CSR has no side effects.
+        unsafe { core::arch::asm!(concat!("csrr {0}, ", $csr), out(reg) value) };
+        value
+    }};
+}
+
+macro_rules! write_csr {
+    ($csr:literal, $value:expr) => {
+        core::arch::asm!(concat!("csrw ", $csr, ", {0}"), in(reg) $value)
+    };
+}
+
+/// Generates the trap helpers of a privilege mode from the names of its `xepc`, `xcause` and
+/// `xtval` CSRs.
+macro_rules! trap_helpers {
+    ($epc:literal, $cause:literal, $tval:literal) => {
+        use crate::{atomic_emulation_with_insn, trapped_instruction, EmulationOutcome, RegisterFile};
+
+        /// `cause` exception code of an illegal instruction.
+        pub const ILLEGAL_INSTRUCTION: usize = 2;
+
+        #[doc = concat!("Reads `", $epc, "`, the address of the trapping instruction.")]
+        #[inline(always)]
+        pub fn epc() -> usize {
+            read_csr!($epc)
+        }
+
+        #[doc = concat!("Writes `", $epc, "`, the address execution resumes at.")]
+        ///
+        /// # Safety
+        ///
+        /// Execution resumes at `pc` when returning from the trap.
+        #[inline(always)]
+        pub unsafe fn set_epc(pc: usize) {
+            write_csr!($epc, pc);
+        }
+
+        #[doc = concat!("Reads `", $cause, "`.")]
+        #[inline(always)]
+        pub fn cause() -> usize {
+            read_csr!($cause)
+        }
+
+        #[doc = concat!("Writes `", $cause, "`.")]
+        ///
+        /// # Safety
+        ///
+        /// Trap handlers running afterwards will see the new cause.
+        #[inline(always)]
+        pub unsafe fn set_cause(cause: usize) {
+            write_csr!($cause, cause);
+        }
+
+        #[doc = concat!("Reads `", $tval, "`.")]
+        #[inline(always)]
+        pub fn tval() -> usize {
+            read_csr!($tval)
+        }
+
+        #[doc = concat!("Writes `", $tval, "`.")]
+        ///
+        /// # Safety
+        ///
+        /// Trap handlers running afterwards will see the new value.
+        #[inline(always)]
+        pub unsafe fn set_tval(tval: usize) {
+            write_csr!($tval, tval);
+        }
+
+        /// Returns true if the trap being handled is an interrupt.
+        #[inline(always)]
+        pub fn is_interrupt() -> bool {
+            (cause() as isize) < 0
+        }
+
+        /// Emulates the instruction that raised the current trap, if it is an illegal instruction
+        /// trap.
+        ///
+        #[doc = concat!("The instruction is taken from `", $tval, "` when the core reports it")]
+        #[doc = concat!("there, and fetched from `", $epc, "` otherwise.")]
+        ///
+        #[doc = concat!("Returns `None` for any other trap. Once emulated, `", $epc, "` is moved")]
+        #[doc = concat!("past the instruction. Genuine faults rewrite `", $cause, "` and `", $tval, "`")]
+        /// to what hardware implementing the instruction would have reported, so the trap can be
+        /// passed on to the regular exception handler.
+        ///
+        /// # Safety
+        ///
+        /// Must be called from a trap handler, with `frame` holding the registers saved on entry.
+        /// See [`atomic_emulation`](crate::atomic_emulation).
+        pub unsafe fn emulate_trap<R: RegisterFile>(
+            frame: &mut R,
+        ) -> Option<EmulationOutcome> {
+            if is_interrupt() || cause() != ILLEGAL_INSTRUCTION {
+                return None;
+            }
+
+            let pc = epc();
+            let insn = trapped_instruction(pc, tval());
+            let outcome = atomic_emulation_with_insn(pc, insn, frame);
+            match outcome {
+                EmulationOutcome::Emulated { next_pc } => set_epc(next_pc),
+                EmulationOutcome::MisalignedAddress { .. }
+                | EmulationOutcome::AccessFault { .. } => {
+                    set_cause(outcome.exception_code().unwrap_or(ILLEGAL_INSTRUCTION));
+                    set_tval(outcome.trap_value());
+                }
+                EmulationOutcome::NotAtomic | EmulationOutcome::IllegalEncoding => {}
+            }
+            Some(outcome)
+        }
+    };
+}
@@ -0,0 +1,212 @@
+//! Decoder for the RISC-V "A" extension instruction encodings.
+//!
+//! This is the exact decoder used by [`atomic_emulation`](crate::atomic_emulation), exposed so that
+//! fault loggers, disassemblers and tests can share the same view of the bit layout.
+//!
+//! ```
+//! use riscv_atomic_emulation_trap::decode::{decode, AtomicInsn, Width};
+//!
+//! // amoadd.w.aq a0, a1, (a2)
+//! let insn = decode(0x04b6252f).unwrap();
+//! assert!(matches!(insn, AtomicInsn::AmoAdd(_)));
+//!
+//! let ops = insn.operands();
+//! assert_eq!(ops.width, Width::Word);
+//! assert!(ops.aq && !ops.rl);
+//! assert_eq!((ops.rd, ops.rs1, ops.rs2), (10, 12, 11));
+//! ```
+
+use core::sync::atomic::Ordering;
+
+/// Major opcode shared by every instruction of the atomic extension.
+pub const OPCODE_AMO: u32 = 0b0101111;
+
+const REG_MASK: u32 = 0b11111;
+
+/// Width of the memory access, encoded in `funct3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Width {
+    /// 8-bit access, `.b` suffix, from the Zabha extension.
+    Byte,
+    /// 16-bit access, `.h` suffix, from the Zabha extension.
+    Half,
+    /// 32-bit access, `.w` suffix.
+    Word,
+    /// 64-bit access, `.d` suffix.
+    Double,
+    /// 128-bit access, `.q` suffix, only valid for `AMOCAS`.
+    Quad,
+}
+
+impl Width {
+    /// Size of the memory access in bytes.
+    #[inline(always)]
+    pub const fn bytes(self) -> usize {
+        match self {
+            Width::Byte => 1,
+            Width::Half => 2,
+            Width::Word => 4,
+            Width::Double => 8,
+            Width::Quad => 16,
+        }
+    }
+}
+
+/// Fields common to every atomic instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Operands {
+    /// Width of the memory access.
+    pub width: Width,
+    /// Acquire bit (bit 26).
+    pub aq: bool,
+    /// Release bit (bit 25).
+    pub rl: bool,
+    /// Destination register.
+    pub rd: usize,
+    /// Address register.
+    pub rs1: usize,
+    /// Source register, always zero for `LR`.
+    pub rs2: usize,
+}
+
+impl Operands {
+    /// Memory ordering requested by the `aq` and `rl` bits, setting both makes the access
+    /// sequentially consistent.
+    #[inline(always)]
+    pub const fn ordering(&self) -> Ordering {
+        match (self.aq, self.rl) {
+            (false, false) => Ordering::Relaxed,
+            (true, false) => Ordering::Acquire,
+            (false, true) => Ordering::Release,
+            (true, true) => Ordering::SeqCst,
+        }
+    }
+}
+
+/// A decoded atomic instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AtomicInsn {
+    Lr(Operands),
+    Sc(Operands),
+    AmoSwap(Operands),
+    AmoAdd(Operands),
+    AmoXor(Operands),
+    AmoAnd(Operands),
+    AmoOr(Operands),
+    AmoMin(Operands),
+    AmoMax(Operands),
+    AmoMinu(Operands),
+    AmoMaxu(Operands),
+    /// `AMOCAS` from the Zacas extension, `rd` holds the expected value and receives the loaded
+    /// one.
+    AmoCas(Operands),
+}
+
+impl AtomicInsn {
+    /// Returns the operand fields of the instruction.
+    #[inline(always)]
+    pub const fn operands(&self) -> &Operands {
+        match self {
+            AtomicInsn::Lr(ops)
+            | AtomicInsn::Sc(ops)
+            | AtomicInsn::AmoSwap(ops)
+            | AtomicInsn::AmoAdd(ops)
+            | AtomicInsn::AmoXor(ops)
+            | AtomicInsn::AmoAnd(ops)
+            | AtomicInsn::AmoOr(ops)
+            | AtomicInsn::AmoMin(ops)
+            | AtomicInsn::AmoMax(ops)
+            | AtomicInsn::AmoMinu(ops)
+            | AtomicInsn::AmoMaxu(ops)
+            | AtomicInsn::AmoCas(ops) => ops,
+        }
+    }
+}
+
+/// Reasons an instruction word could not be decoded as an atomic instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The major opcode is not [`OPCODE_AMO`].
+    NotAtomic,
+    /// `funct3` does not name a supported access width.
+    InvalidWidth,
+    /// `funct5` does not name a known operation.
+    InvalidOperation,
+    /// A reserved field is not zero, e.g. `rs2` of `LR`.
+    InvalidEncoding,
+}
+
+/// Returns the length in bytes of the instruction starting with the 16-bit `parcel`, following
+/// the variable length encoding of the base ISA, or `None` for the reserved 192-bit and longer
+/// encodings.
+///
+/// Atomic instructions are always 4 bytes long, 2 bytes means a compressed instruction.
+pub const fn instruction_length(parcel: u16) -> Option<usize> {
+    if parcel & 0b11 != 0b11 {
+        Some(2)
+    } else if parcel & 0b11100 != 0b11100 {
+        Some(4)
+    } else if parcel & 0b111111 == 0b011111 {
+        Some(6)
+    } else if parcel & 0b1111111 == 0b0111111 {
+        Some(8)
+    } else if parcel & 0b1111111 == 0b1111111 && (parcel >> 12) & 0b111 != 0b111 {
+        Some(10 + 2 * ((parcel >> 12) & 0b111) as usize)
+    } else {
+        None
+    }
+}
+
+/// Decodes a 32-bit instruction word.
+pub fn decode(insn: u32) -> Result<AtomicInsn, DecodeError> {
+    // also rules out compressed and longer instructions, whose low bits never match the opcode
+    if insn & 0b1111111 != OPCODE_AMO {
+        return Err(DecodeError::NotAtomic);
+    }
+
+    let width = match (insn >> 12) & 0b111 {
+        0b000 => Width::Byte,
+        0b001 => Width::Half,
+        0b010 => Width::Word,
+        0b011 => Width::Double,
+        0b100 => Width::Quad,
+        _ => return Err(DecodeError::InvalidWidth),
+    };
+
+    let ops = Operands {
+        width,
+        aq: (insn >> 26) & 1 != 0,
+        rl: (insn >> 25) & 1 != 0,
+        rd: ((insn >> 7) & REG_MASK) as usize,
+        rs1: ((insn >> 15) & REG_MASK) as usize,
+        rs2: ((insn >> 20) & REG_MASK) as usize,
+    };
+
+    let funct5 = insn >> 27;
+    let valid_width = match width {
+        // Zabha has no byte or halfword LR/SC
+        Width::Byte | Width::Half => !matches!(funct5, 0b00010 | 0b00011),
+        Width::Word | Width::Double => true,
+        Width::Quad => funct5 == 0b00101,
+    };
+    if !valid_width {
+        return Err(DecodeError::InvalidWidth);
+    }
+
+    Ok(match funct5 {
+        0b00010 if ops.rs2 != 0 => return Err(DecodeError::InvalidEncoding),
+        0b00010 => AtomicInsn::Lr(ops),
+        0b00011 => AtomicInsn::Sc(ops),
+        0b00001 => AtomicInsn::AmoSwap(ops),
+        0b00000 => AtomicInsn::AmoAdd(ops),
+        0b00100 => AtomicInsn::AmoXor(ops),
+        0b01100 => AtomicInsn::AmoAnd(ops),
+        0b01000 => AtomicInsn::AmoOr(ops),
+        0b10000 => AtomicInsn::AmoMin(ops),
+        0b10100 => AtomicInsn::AmoMax(ops),
+        0b11000 => AtomicInsn::AmoMinu(ops),
+        0b11100 => AtomicInsn::AmoMaxu(ops),
+        0b00101 => AtomicInsn::AmoCas(ops),
+        _ => return Err(DecodeError::InvalidOperation),
+    })
+}
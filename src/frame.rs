@@ -0,0 +1,322 @@
+//! Register frames saved by trap entries.
+//!
+//! [`TrapFrame`] holds the general purpose registers in `x0-x31` order, the layout
+//! [`atomic_emulation`](crate::atomic_emulation) works on, with accessors named after the ABI.
+//! The frames of `riscv-rt` and `esp-hal` save the registers in ABI order instead, their layouts
+//! are mirrored by [`RiscvRtTrapFrame`] and [`EspHalTrapFrame`], which can be cast from a pointer
+//! to the original frame. The conversions between them and [`TrapFrame`] copy every register.
+//!
+//! `riscv-rt`'s frame only holds the caller saved registers, too few to emulate on: a trap frame
+//! built from it would hand the emulator zeros for `sp` or `s0`. [`TrapFrame`] only converts into
+//! it, to pass the registers on to `riscv-rt`'s handlers.
+//!
+//! The emulator itself works on any [`RegisterFile`], so a frame already saved in another layout
+//! can be used in place, without copies, by implementing the trait for it, as is done for
+//! [`EspHalTrapFrame`].
+
+use crate::PLATFORM_REGISTER_LEN;
+
+/// Access to saved general purpose registers by number.
+///
+/// `x0` must read as zero and ignore writes, whatever the frame stores in its place.
+pub trait RegisterFile {
+    /// Number of registers, `x0` to `x{LEN - 1}`, instructions naming others are not emulated.
+    const LEN: usize;
+
+    /// Reads `x{xreg}`.
+    fn read(&self, xreg: usize) -> usize;
+    /// Writes `x{xreg}`.
+    fn write(&mut self, xreg: usize, value: usize);
+}
+
+/// Registers in `x0-x{N - 1}` order, `x0` included, typically [`PLATFORM_REGISTER_LEN`] long.
+impl<const N: usize> RegisterFile for [usize; N] {
+    const LEN: usize = N;
+
+    #[inline(always)]
+    fn read(&self, xreg: usize) -> usize {
+        if xreg == 0 {
+            0
+        } else {
+            self[xreg]
+        }
+    }
+
+    #[inline(always)]
+    fn write(&mut self, xreg: usize, value: usize) {
+        if xreg != 0 {
+            self[xreg] = value;
+        }
+    }
+}
+
+/// The general purpose registers at the time of the trap, in `x0-x31` order.
+///
+/// `x0` is stored as zero.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrapFrame {
+    x: [usize; PLATFORM_REGISTER_LEN],
+}
+
+impl Default for TrapFrame {
+    fn default() -> Self {
+        Self::new([0; PLATFORM_REGISTER_LEN])
+    }
+}
+
+impl TrapFrame {
+    /// Creates a frame from registers in `x0-x31` order, `x0` is cleared.
+    pub const fn new(mut x: [usize; PLATFORM_REGISTER_LEN]) -> Self {
+        x[0] = 0;
+        Self { x }
+    }
+
+    /// The registers in `x0-x31` order, as taken by
+    /// [`atomic_emulation`](crate::atomic_emulation).
+    #[inline(always)]
+    pub fn registers(&self) -> &[usize; PLATFORM_REGISTER_LEN] {
+        &self.x
+    }
+
+    /// The registers in `x0-x31` order, as taken by
+    /// [`atomic_emulation`](crate::atomic_emulation).
+    #[inline(always)]
+    pub fn registers_mut(&mut self) -> &mut [usize; PLATFORM_REGISTER_LEN] {
+        &mut self.x
+    }
+}
+
+impl RegisterFile for TrapFrame {
+    const LEN: usize = PLATFORM_REGISTER_LEN;
+
+    #[inline(always)]
+    fn read(&self, xreg: usize) -> usize {
+        self.x.read(xreg)
+    }
+
+    #[inline(always)]
+    fn write(&mut self, xreg: usize, value: usize) {
+        self.x.write(xreg, value)
+    }
+}
+
+impl From<[usize; PLATFORM_REGISTER_LEN]> for TrapFrame {
+    fn from(x: [usize; PLATFORM_REGISTER_LEN]) -> Self {
+        Self::new(x)
+    }
+}
+
+macro_rules! accessors {
+    ($($name:ident, $set:ident = $x:literal;)*) => {
+        impl TrapFrame {
+            $(
+                #[doc = concat!("Reads `", stringify!($name), "`, `x", $x, "`.")]
+                #[inline(always)]
+                pub fn $name(&self) -> usize {
+                    self.x[$x]
+                }
+
+                #[doc = concat!("Writes `", stringify!($name), "`, `x", $x, "`.")]
+                #[inline(always)]
+                pub fn $set(&mut self, value: usize) {
+                    self.x[$x] = value;
+                }
+            )*
+        }
+    };
+}
+
+accessors! {
+    ra, set_ra = 1;
+    sp, set_sp = 2;
+    gp, set_gp = 3;
+    tp, set_tp = 4;
+    t0, set_t0 = 5;
+    t1, set_t1 = 6;
+    t2, set_t2 = 7;
+    s0, set_s0 = 8;
+    s1, set_s1 = 9;
+    a0, set_a0 = 10;
+    a1, set_a1 = 11;
+    a2, set_a2 = 12;
+    a3, set_a3 = 13;
+    a4, set_a4 = 14;
+    a5, set_a5 = 15;
+}
+
+// registers missing from the reduced register file
+#[cfg(not(target_feature = "e"))]
+accessors! {
+    a6, set_a6 = 16;
+    a7, set_a7 = 17;
+    s2, set_s2 = 18;
+    s3, set_s3 = 19;
+    s4, set_s4 = 20;
+    s5, set_s5 = 21;
+    s6, set_s6 = 22;
+    s7, set_s7 = 23;
+    s8, set_s8 = 24;
+    s9, set_s9 = 25;
+    s10, set_s10 = 26;
+    s11, set_s11 = 27;
+    t3, set_t3 = 28;
+    t4, set_t4 = 29;
+    t5, set_t5 = 30;
+    t6, set_t6 = 31;
+}
+
+/// `riscv-rt`'s `TrapFrame`, the caller saved registers.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RiscvRtTrapFrame {
+    pub ra: usize,
+    pub t0: usize,
+    pub t1: usize,
+    pub t2: usize,
+    pub t3: usize,
+    pub t4: usize,
+    pub t5: usize,
+    pub t6: usize,
+    pub a0: usize,
+    pub a1: usize,
+    pub a2: usize,
+    pub a3: usize,
+    pub a4: usize,
+    pub a5: usize,
+    pub a6: usize,
+    pub a7: usize,
+}
+
+/// `esp-hal`'s `TrapFrame`, every register but `x0` followed by the trap CSRs.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EspHalTrapFrame {
+    pub ra: usize,
+    pub t0: usize,
+    pub t1: usize,
+    pub t2: usize,
+    pub t3: usize,
+    pub t4: usize,
+    pub t5: usize,
+    pub t6: usize,
+    pub a0: usize,
+    pub a1: usize,
+    pub a2: usize,
+    pub a3: usize,
+    pub a4: usize,
+    pub a5: usize,
+    pub a6: usize,
+    pub a7: usize,
+    pub s0: usize,
+    pub s1: usize,
+    pub s2: usize,
+    pub s3: usize,
+    pub s4: usize,
+    pub s5: usize,
+    pub s6: usize,
+    pub s7: usize,
+    pub s8: usize,
+    pub s9: usize,
+    pub s10: usize,
+    pub s11: usize,
+    pub gp: usize,
+    pub tp: usize,
+    pub sp: usize,
+    pub pc: usize,
+    pub mstatus: usize,
+    pub mcause: usize,
+    pub mtval: usize,
+}
+
+macro_rules! register_file {
+    ($frame:ty, $len:literal, [$($x:literal => $reg:ident),*]) => {
+        impl RegisterFile for $frame {
+            const LEN: usize = $len;
+
+            #[inline(always)]
+            fn read(&self, xreg: usize) -> usize {
+                match xreg {
+                    $($x => self.$reg,)*
+                    _ => 0,
+                }
+            }
+
+            #[inline(always)]
+            fn write(&mut self, xreg: usize, value: usize) {
+                match xreg {
+                    $($x => self.$reg = value,)*
+                    _ => {}
+                }
+            }
+        }
+    };
+}
+
+// emulates in place, without converting to a `TrapFrame`
+register_file!(EspHalTrapFrame, 32, [
+    1 => ra, 2 => sp, 3 => gp, 4 => tp, 5 => t0, 6 => t1, 7 => t2, 8 => s0, 9 => s1, 10 => a0,
+    11 => a1, 12 => a2, 13 => a3, 14 => a4, 15 => a5, 16 => a6, 17 => a7, 18 => s2, 19 => s3,
+    20 => s4, 21 => s5, 22 => s6, 23 => s7, 24 => s8, 25 => s9, 26 => s10, 27 => s11, 28 => t3,
+    29 => t4, 30 => t5, 31 => t6
+]);
+
+#[cfg(not(target_feature = "e"))]
+mod conversions {
+    use super::{EspHalTrapFrame, RiscvRtTrapFrame, TrapFrame};
+
+    macro_rules! from_abi {
+        ($frame:ident, [$($reg:ident => $set:ident),*]) => {{
+            let mut x = TrapFrame::default();
+            $(x.$set($frame.$reg);)*
+            x
+        }};
+    }
+
+    macro_rules! to_abi {
+        ($x:ident, $frame:ident, [$($reg:ident),*]) => {
+            $($frame.$reg = $x.$reg();)*
+        };
+    }
+
+    /// Keeps the caller saved registers, the others are not part of `riscv-rt`'s frame.
+    impl From<&TrapFrame> for RiscvRtTrapFrame {
+        fn from(x: &TrapFrame) -> Self {
+            let mut frame = Self::default();
+            to_abi!(
+                x,
+                frame,
+                [ra, t0, t1, t2, t3, t4, t5, t6, a0, a1, a2, a3, a4, a5, a6, a7]
+            );
+            frame
+        }
+    }
+
+    impl From<&EspHalTrapFrame> for TrapFrame {
+        fn from(frame: &EspHalTrapFrame) -> Self {
+            from_abi!(frame, [
+                ra => set_ra, sp => set_sp, gp => set_gp, tp => set_tp, t0 => set_t0,
+                t1 => set_t1, t2 => set_t2, s0 => set_s0, s1 => set_s1, a0 => set_a0,
+                a1 => set_a1, a2 => set_a2, a3 => set_a3, a4 => set_a4, a5 => set_a5,
+                a6 => set_a6, a7 => set_a7, s2 => set_s2, s3 => set_s3, s4 => set_s4,
+                s5 => set_s5, s6 => set_s6, s7 => set_s7, s8 => set_s8, s9 => set_s9,
+                s10 => set_s10, s11 => set_s11, t3 => set_t3, t4 => set_t4, t5 => set_t5,
+                t6 => set_t6
+            ])
+        }
+    }
+
+    impl TrapFrame {
+        /// Writes the registers back to `esp-hal`'s frame, leaving `pc` and the CSRs untouched.
+        pub fn write_to_esp_hal(&self, frame: &mut EspHalTrapFrame) {
+            to_abi!(
+                self,
+                frame,
+                [
+                    ra, sp, gp, tp, t0, t1, t2, s0, s1, a0, a1, a2, a3, a4, a5, a6, a7, s2, s3, s4,
+                    s5, s6, s7, s8, s9, s10, s11, t3, t4, t5, t6
+                ]
+            );
+        }
+    }
+}
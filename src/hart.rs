@@ -0,0 +1,83 @@
+//! Identification of the hart the emulator runs on.
+//!
+//! The number of harts the emulator's state is sized for is [`HARTS`], selected with the
+//! `harts-2`, `harts-4` and `harts-8` features.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of harts the emulator keeps state for.
+#[cfg(feature = "harts-8")]
+pub const HARTS: usize = 8;
+#[cfg(all(feature = "harts-4", not(feature = "harts-8")))]
+pub const HARTS: usize = 4;
+#[cfg(all(
+    feature = "harts-2",
+    not(any(feature = "harts-4", feature = "harts-8"))
+))]
+pub const HARTS: usize = 2;
+#[cfg(not(any(feature = "harts-2", feature = "harts-4", feature = "harts-8")))]
+pub const HARTS: usize = 1;
+
+/// `fn() -> usize` returning the current hart id, or 0 for the default.
+static HART_ID_SOURCE: AtomicUsize = AtomicUsize::new(0);
+
+/// Overrides how the current hart id is determined.
+///
+/// By default the id is read from `mhartid` when more than one hart is tracked. In supervisor mode
+/// there is no default, see the `supervisor` module. Code numbering its harts differently should
+/// provide its own source too.
+pub fn set_hart_id_source(source: fn() -> usize) {
+    HART_ID_SOURCE.store(source as usize, Ordering::Relaxed);
+}
+
+/// Returns the id of the hart the emulator is running on.
+///
+/// [`UNKNOWN_HART`] when it cannot be determined, which no reservation or lock can serve.
+#[inline]
+pub fn hart_id() -> usize {
+    match HART_ID_SOURCE.load(Ordering::Relaxed) {
+        0 => default_hart_id(),
+        // SAFETY: only ever set from a `fn() -> usize` in `set_hart_id_source`.
+        source => unsafe { core::mem::transmute::<usize, fn() -> usize>(source)() },
+    }
+}
+
+/// The id of a hart that cannot be told apart from the others.
+pub const UNKNOWN_HART: usize = usize::MAX;
+
+#[cfg(all(
+    any(target_arch = "riscv32", target_arch = "riscv64"),
+    not(feature = "supervisor")
+))]
+#[inline(always)]
+fn default_hart_id() -> usize {
+    if HARTS == 1 {
+        return 0;
+    }
+
+    let id: usize;
+    // SAFETY: reading `mhartid` has no side effects.
+    unsafe { core::arch::asm!("csrr {0}, mhartid", out(reg) id) };
+    id
+}
+
+// `mhartid` traps in S-mode and `tp` may hold the thread pointer of whatever trapped, only the
+// kernel knows the hart
+#[cfg(all(
+    any(target_arch = "riscv32", target_arch = "riscv64"),
+    feature = "supervisor"
+))]
+#[inline(always)]
+fn default_hart_id() -> usize {
+    if HARTS == 1 {
+        0
+    } else {
+        UNKNOWN_HART
+    }
+}
+
+#[cfg(not(any(target_arch = "riscv32", target_arch = "riscv64")))]
+#[inline(always)]
+fn default_hart_id() -> usize {
+    0
+}
@@ -1,27 +1,320 @@
 #![doc = include_str!("../README.md")]
 #![cfg_attr(not(test), no_std)]
+// without either instruction group nothing is emulated and the emulation code goes unused
+#![cfg_attr(
+    not(any(feature = "zalrsc", feature = "zaamo")),
+    allow(dead_code, unused_variables)
+)]
 
-pub const PLATFORM_REGISTER_LEN: usize = 32; // TODO will be less on r32e, handle at somepoint
+#[cfg(feature = "alloc")]
+extern crate alloc;
 
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+#[macro_use]
+mod csr;
+
+pub mod decode;
+pub mod frame;
+pub mod hart;
+pub mod lock;
+#[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))]
+pub mod machine;
+pub mod memory;
+pub mod region;
+pub mod reservation;
+#[cfg(all(
+    feature = "supervisor",
+    any(target_arch = "riscv32", target_arch = "riscv64")
+))]
+pub mod supervisor;
+#[cfg(all(
+    feature = "riscv-rt",
+    any(target_arch = "riscv32", target_arch = "riscv64")
+))]
+mod trap;
+
+use core::ops::{BitAnd, BitOr, BitXor};
+use core::sync::atomic::{self, Ordering};
+
+use decode::{decode, AtomicInsn, DecodeError, Operands, Width};
+pub use frame::{RegisterFile, TrapFrame};
+pub use lock::{set_emulation_lock, EmulationLock};
+use memory::{BusError, MemoryBus, RawMemory, Word};
+pub use reservation::{invalidate_reservation, ReservationPolicy};
+
+/// Number of general purpose registers, `x0-x31`.
+#[cfg(not(target_feature = "e"))]
+pub const PLATFORM_REGISTER_LEN: usize = 32;
+/// Number of general purpose registers on the reduced RV32E/RV64E register file, `x0-x15`.
+#[cfg(target_feature = "e")]
+pub const PLATFORM_REGISTER_LEN: usize = E_REGISTER_LEN;
+
+/// Number of general purpose registers on the reduced RV32E/RV64E register file.
+pub const E_REGISTER_LEN: usize = 16;
+
+#[cfg(feature = "zaamo")]
 macro_rules! amo {
-    ($frame:ident, $rs1:ident, $rs2:ident, $rd:ident, $operation:expr) => {
-        let tmp = $frame[$rs1];
-        let a = *(tmp as *const _);
-        let b = $frame[$rs2];
-        $frame[$rd] = a;
-        *(tmp as *mut _) = $operation(a, b);
+    ($bus:ident, $frame:ident, $rs1:ident, $rs2:ident, $rd:ident, $ty:ty, $operation:expr) => {
+        let tmp = $frame.read($rs1);
+        let a = <$ty>::load($bus, tmp)?;
+        let b = <$ty>::from_reg($frame.read($rs2));
+        $operation(a, b).store($bus, tmp)?;
+        $frame.write($rd, a.to_reg());
+    };
+}
+
+/// An integer the width of an atomic memory access.
+trait Value:
+    Copy + Ord + BitXor<Output = Self> + BitAnd<Output = Self> + BitOr<Output = Self> + Word
+{
+    /// Truncates a register value to the access width.
+    fn from_reg(reg: usize) -> Self;
+    /// Sign-extends the value to the register width, as loads into `rd` do.
+    fn to_reg(self) -> usize;
+    /// Adds with the wrapping semantics of `AMOADD`, regardless of `overflow-checks`.
+    #[cfg(feature = "zaamo")]
+    fn wrapping_add(self, other: Self) -> Self;
+    #[cfg(feature = "zaamo")]
+    fn signed_min(self, other: Self) -> Self;
+    #[cfg(feature = "zaamo")]
+    fn signed_max(self, other: Self) -> Self;
+    /// Combines the halves held by an even/odd register pair, truncated to the access width.
+    #[cfg(feature = "zaamo")]
+    fn from_pair(lo: usize, hi: usize) -> Self;
+    /// Splits the value over an even/odd register pair, low half first.
+    #[cfg(feature = "zaamo")]
+    fn to_pair(self) -> (usize, usize);
+}
+
+macro_rules! impl_value {
+    ($ty:ty, $signed:ty) => {
+        impl Value for $ty {
+            #[inline(always)]
+            fn from_reg(reg: usize) -> Self {
+                reg as $ty
+            }
+
+            #[inline(always)]
+            fn to_reg(self) -> usize {
+                self as $signed as isize as usize
+            }
+
+            #[cfg(feature = "zaamo")]
+            #[inline(always)]
+            fn wrapping_add(self, other: Self) -> Self {
+                <$ty>::wrapping_add(self, other)
+            }
+
+            #[cfg(feature = "zaamo")]
+            #[inline(always)]
+            fn signed_min(self, other: Self) -> Self {
+                (self as $signed).min(other as $signed) as $ty
+            }
+
+            #[cfg(feature = "zaamo")]
+            #[inline(always)]
+            fn signed_max(self, other: Self) -> Self {
+                (self as $signed).max(other as $signed) as $ty
+            }
+
+            #[cfg(feature = "zaamo")]
+            #[inline(always)]
+            fn from_pair(lo: usize, hi: usize) -> Self {
+                ((hi as u128) << usize::BITS | lo as u128) as $ty
+            }
+
+            #[cfg(feature = "zaamo")]
+            #[inline(always)]
+            fn to_pair(self) -> (usize, usize) {
+                (self as usize, (self as u128 >> usize::BITS) as usize)
+            }
+        }
     };
 }
 
+impl_value!(u8, i8);
+impl_value!(u16, i16);
+impl_value!(u32, i32);
+impl_value!(u64, i64);
+#[cfg(target_pointer_width = "64")]
+impl_value!(u128, i128);
+
+/// Returns true if `AMOCAS` operands of type `T` span an even/odd register pair.
+#[cfg(feature = "zaamo")]
+#[inline(always)]
+const fn is_pair<T>() -> bool {
+    core::mem::size_of::<T>() > core::mem::size_of::<usize>()
+}
+
+/// Reads an `AMOCAS` operand from `r`, or from the pair starting at `r` when [`is_pair`].
+/// The `x0` pair reads as zero.
+#[cfg(feature = "zaamo")]
+#[inline(always)]
+fn read_cas<T: Value, R: RegisterFile>(frame: &R, r: usize) -> T {
+    if !is_pair::<T>() {
+        T::from_reg(frame.read(r))
+    } else if r == 0 {
+        T::from_pair(0, 0)
+    } else {
+        T::from_pair(frame.read(r), frame.read(r + 1))
+    }
+}
+
+/// Writes the `AMOCAS` result to `rd`, or to the pair starting at `rd` when [`is_pair`].
+/// Writes to the `x0` pair are discarded.
+#[cfg(feature = "zaamo")]
+#[inline(always)]
+fn write_cas<T: Value, R: RegisterFile>(frame: &mut R, rd: usize, value: T) {
+    if !is_pair::<T>() {
+        frame.write(rd, value.to_reg());
+    } else if rd != 0 {
+        let (lo, hi) = value.to_pair();
+        frame.write(rd, lo);
+        frame.write(rd + 1, hi);
+    }
+}
+
 /// Checks if the instruction is an atomic one.
 #[inline(always)]
 pub fn is_atomic_instruction(insn: usize) -> bool {
-    (insn & 0b1111111) == 0b0101111
+    (insn as u32 & 0b1111111) == decode::OPCODE_AMO
+}
+
+/// Length in bytes of every atomic instruction.
+pub const INSN_LEN: usize = 4;
+
+/// Result of [`atomic_emulation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmulationOutcome {
+    /// The instruction was emulated, execution resumes at `next_pc`.
+    Emulated { next_pc: usize },
+    /// The instruction is not an atomic one, belongs to a group the emulator is built without, or
+    /// the [emulation lock](lock) or the [reservation set](reservation) cannot serve the current
+    /// hart, the trap is not for the emulator to handle.
+    NotAtomic,
+    /// The instruction is atomic but uses a reserved encoding, or one the target cannot execute.
+    IllegalEncoding,
+    /// The memory address is not naturally aligned for the access width.
+    MisalignedAddress { addr: usize, access: Access },
+    /// The memory address cannot be accessed.
+    AccessFault { addr: usize, access: Access },
+}
+
+/// Kind of memory access that faulted, deciding which exception is raised.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// `LR`, faults as a load.
+    Load,
+    /// `SC` and AMOs, fault as a store/AMO.
+    Store,
+}
+
+impl EmulationOutcome {
+    /// Returns true if the instruction was emulated.
+    #[inline(always)]
+    pub const fn is_emulated(&self) -> bool {
+        matches!(self, EmulationOutcome::Emulated { .. })
+    }
+
+    /// The `mcause` exception code hardware with the atomic extension would have raised, if any.
+    pub const fn exception_code(&self) -> Option<usize> {
+        match self {
+            EmulationOutcome::Emulated { .. } | EmulationOutcome::NotAtomic => None,
+            EmulationOutcome::IllegalEncoding => Some(2),
+            EmulationOutcome::MisalignedAddress {
+                access: Access::Load,
+                ..
+            } => Some(4),
+            EmulationOutcome::AccessFault {
+                access: Access::Load,
+                ..
+            } => Some(5),
+            EmulationOutcome::MisalignedAddress {
+                access: Access::Store,
+                ..
+            } => Some(6),
+            EmulationOutcome::AccessFault {
+                access: Access::Store,
+                ..
+            } => Some(7),
+        }
+    }
+
+    /// The `mtval` value accompanying [`exception_code`](Self::exception_code): the faulting
+    /// address, or zero.
+    pub const fn trap_value(&self) -> usize {
+        match self {
+            EmulationOutcome::MisalignedAddress { addr, .. }
+            | EmulationOutcome::AccessFault { addr, .. } => *addr,
+            _ => 0,
+        }
+    }
+}
+
+/// Reads the instruction at `pc`.
+///
+/// With the compressed extension a 32-bit instruction only needs to be 16-bit aligned, so the
+/// instruction is read one 16-bit parcel at a time: the second parcel is only read if the first
+/// one announces a 32-bit instruction. Other lengths return the first parcel alone, which never
+/// decodes as an atomic instruction.
+///
+/// # Safety
+///
+/// `pc` must be 16-bit aligned and point to readable memory holding an instruction.
+#[inline(always)]
+pub unsafe fn fetch_instruction(pc: usize) -> u32 {
+    let low = (pc as *const u16).read();
+    match decode::instruction_length(low) {
+        Some(4) => {
+            let high = (pc.wrapping_add(2) as *const u16).read();
+            (high as u32) << 16 | low as u32
+        }
+        _ => low as u32,
+    }
 }
 
-/// Takes the program counter address that triggered the exception and an array of
-/// registers at point of exception with [`PLATFORM_REGISTER_LEN`] length.
-/// Returns true if the instruction was atomic and was emulated, false otherwise.
+/// Returns the instruction that raised an illegal instruction trap at `pc`.
+///
+/// Many cores report the faulting instruction in `mtval`/`stval`, which avoids fetching it from
+/// memory that may be execute-only, protected or not coherent with the data bus. `tval` is used
+/// when it is non-zero, the instruction is fetched from `pc` otherwise.
+///
+/// # Safety
+///
+/// When `tval` is zero, `pc` must point to readable memory holding an instruction.
+#[inline]
+pub unsafe fn trapped_instruction(pc: usize, tval: usize) -> u32 {
+    match tval {
+        0 => fetch_instruction(pc),
+        insn => insn as u32,
+    }
+}
+
+/// Takes the program counter address that triggered the exception and the registers at point of
+/// exception, usually an array of [`PLATFORM_REGISTER_LEN`] registers in `x0-x31` order or a
+/// [`TrapFrame`]. Frames saved in another layout can be used directly by implementing
+/// [`RegisterFile`].
+///
+/// Returns [`EmulationOutcome::Emulated`] with the address of the next instruction if the
+/// instruction was atomic and was emulated, the program counter must be moved there before
+/// returning from the trap. Any other outcome leaves the frame and memory untouched and should be
+/// reported to the user's exception handler.
+///
+/// Cores with the reduced register file can pass a frame of [`E_REGISTER_LEN`] registers, encodings
+/// naming a register outside of the frame are treated as illegal and are not emulated.
+///
+/// The access width is taken from the instruction: `.b`, `.h` and `.w` operations access 8, 16 and
+/// 32 bits of memory and sign-extend the loaded value into `rd`, `.d` operations are only emulated
+/// on 64-bit targets.
+/// The exception is Zacas' `AMOCAS.D` on 32-bit targets, and `AMOCAS.Q` on 64-bit ones, whose
+/// `rd` and `rs2` name even/odd register pairs holding the low and high halves.
+/// Addresses that are not naturally aligned for the width are reported as
+/// [`EmulationOutcome::MisalignedAddress`] without being accessed.
+///
+/// The `aq` and `rl` bits are honoured with `fence` instructions around the emulated access.
+///
+/// Only the instruction groups selected by the `zalrsc` (LR/SC) and `zaamo` (AMOs) features are
+/// emulated, the others are reported as [`EmulationOutcome::NotAtomic`].
 ///
 /// # Safety
 ///
@@ -29,79 +322,238 @@ pub fn is_atomic_instruction(insn: usize) -> bool {
 /// Thus, it assumes that the program counter is valid and points to a valid instruction.
 /// It also assumes that all the user registers were correctly saved and sorted in a trap frame.
 #[inline]
-pub unsafe fn atomic_emulation(pc: usize, frame: &mut [usize; PLATFORM_REGISTER_LEN]) -> bool {
-    static mut S_LR_ADDR: usize = 0;
-
+pub unsafe fn atomic_emulation<R: RegisterFile>(pc: usize, frame: &mut R) -> EmulationOutcome {
     // SAFETY: program counter is valid and points to a valid instruction.
-    let insn = unsafe { (pc as *const usize).read_unaligned() };
-    if !is_atomic_instruction(insn) {
-        return false;
-    }
-
-    let reg_mask = 0b11111;
-    // destination register
-    let rd = (insn >> 7) & reg_mask;
-    // source 1 register
-    let rs1 = (insn >> 15) & reg_mask;
-    // source 2 register
-    let rs2 = (insn >> 20) & reg_mask;
-
-    match insn >> 27 {
-        0b00010 => {
-            /* LR */
-            S_LR_ADDR = frame[rs1];
-            let tmp: usize = *(S_LR_ADDR as *const _);
-            frame[rd] = tmp;
-        }
-        0b00011 => {
-            /* SC */
-            let tmp: usize = frame[rs1];
-            if tmp != S_LR_ADDR {
-                frame[rd] = 1;
+    let insn = unsafe { fetch_instruction(pc) };
+    atomic_emulation_with_insn(pc, insn, frame)
+}
+
+/// Like [`atomic_emulation`], but emulates `insn` instead of fetching the instruction at `pc`.
+///
+/// Useful when the trapping instruction is known already, typically from `mtval`, see
+/// [`trapped_instruction`]. `pc` is only used to compute the address of the next instruction.
+///
+/// # Safety
+///
+/// This function is supposed to be called right after `insn` caused an exception at `pc`.
+/// It assumes that all the user registers were correctly saved and sorted in a trap frame.
+#[inline]
+pub unsafe fn atomic_emulation_with_insn<R: RegisterFile>(
+    pc: usize,
+    insn: u32,
+    frame: &mut R,
+) -> EmulationOutcome {
+    // SAFETY: the instruction trapped, so its address is valid for the running program.
+    atomic_emulation_with_bus(pc, insn, frame, &mut unsafe { RawMemory::new() })
+}
+
+/// Like [`atomic_emulation_with_insn`], but performs the memory accesses on `bus`.
+///
+/// This lets simulators and tests emulate instructions against their own address space, see the
+/// [`memory`] module. Accesses the bus refuses are reported as [`EmulationOutcome::AccessFault`],
+/// leaving the frame untouched.
+pub fn atomic_emulation_with_bus<R: RegisterFile, M: MemoryBus>(
+    pc: usize,
+    insn: u32,
+    frame: &mut R,
+    bus: &mut M,
+) -> EmulationOutcome {
+    let insn = match decode(insn) {
+        Ok(insn) => insn,
+        Err(DecodeError::NotAtomic) => return EmulationOutcome::NotAtomic,
+        Err(_) => return EmulationOutcome::IllegalEncoding,
+    };
+
+    // groups left out by the `zalrsc` and `zaamo` features are implemented by the core itself,
+    // the code emulating them is not built
+    #[cfg(not(feature = "zalrsc"))]
+    if matches!(insn, AtomicInsn::Lr(_) | AtomicInsn::Sc(_)) {
+        return EmulationOutcome::NotAtomic;
+    }
+    #[cfg(not(feature = "zaamo"))]
+    if !matches!(insn, AtomicInsn::Lr(_) | AtomicInsn::Sc(_)) {
+        return EmulationOutcome::NotAtomic;
+    }
+
+    // an SC failing forever would hang LR/SC loops on harts without a reservation
+    #[cfg(feature = "zalrsc")]
+    if matches!(insn, AtomicInsn::Lr(_) | AtomicInsn::Sc(_)) && !reservation::tracked() {
+        return EmulationOutcome::NotAtomic;
+    }
+
+    let ops = insn.operands();
+    if ops.rd >= R::LEN || ops.rs1 >= R::LEN || ops.rs2 >= R::LEN {
+        return EmulationOutcome::IllegalEncoding;
+    }
+
+    match ops.width {
+        // only Zabha AMOs access bytes and halfwords
+        #[cfg(feature = "zaamo")]
+        Width::Byte => lock::locked(|| emulate::<u8, R, M>(pc, insn, frame, bus)),
+        #[cfg(feature = "zaamo")]
+        Width::Half => lock::locked(|| emulate::<u16, R, M>(pc, insn, frame, bus)),
+        Width::Word => lock::locked(|| emulate::<u32, R, M>(pc, insn, frame, bus)),
+        #[cfg(target_pointer_width = "64")]
+        Width::Double => lock::locked(|| emulate::<u64, R, M>(pc, insn, frame, bus)),
+        // the decoder only accepts `.q` for AMOCAS
+        #[cfg(all(target_pointer_width = "64", feature = "zaamo"))]
+        Width::Quad => lock::locked(|| emulate::<u128, R, M>(pc, insn, frame, bus)),
+        // AMOCAS.D operates on register pairs on RV32
+        #[cfg(all(not(target_pointer_width = "64"), feature = "zaamo"))]
+        Width::Double if matches!(insn, AtomicInsn::AmoCas(_)) => {
+            lock::locked(|| emulate::<u64, R, M>(pc, insn, frame, bus))
+        }
+        // other 64-bit accesses and every 128-bit one are reserved on RV32, the widths of AMOs
+        // left out never reach here
+        #[cfg(any(not(target_pointer_width = "64"), not(feature = "zaamo")))]
+        _ => EmulationOutcome::IllegalEncoding,
+    }
+}
+
+/// Issues the fence giving the emulated access its `aq`/`rl` `ordering`, `before` or after it.
+///
+/// Real `fence` instructions are used even on a single hart: the trap is already a compiler
+/// barrier, what is left to order are the accesses seen by DMA and other bus masters.
+#[inline(always)]
+fn fence(ordering: Ordering, before: bool) {
+    match ordering {
+        Ordering::SeqCst => atomic::fence(Ordering::SeqCst),
+        Ordering::Release if before => atomic::fence(Ordering::Release),
+        Ordering::Acquire if !before => atomic::fence(Ordering::Acquire),
+        _ => {}
+    }
+}
+
+/// Emulates `insn`, located at `pc`, with memory accesses of type `T` on `bus`.
+#[inline(always)]
+fn emulate<T: Value, R: RegisterFile, M: MemoryBus>(
+    pc: usize,
+    insn: AtomicInsn,
+    frame: &mut R,
+    bus: &mut M,
+) -> EmulationOutcome {
+    // register pairs must start at an even register
+    #[cfg(feature = "zaamo")]
+    if matches!(insn, AtomicInsn::AmoCas(_))
+        && is_pair::<T>()
+        && (insn.operands().rd | insn.operands().rs2) & 1 != 0
+    {
+        return EmulationOutcome::IllegalEncoding;
+    }
+
+    // AMOs report faults as stores, even for their load
+    let addr = frame.read(insn.operands().rs1);
+    let access = match insn {
+        AtomicInsn::Lr(_) => Access::Load,
+        _ => Access::Store,
+    };
+
+    // like the hardware, refuse misaligned addresses before touching memory or the reservation
+    if addr & (core::mem::size_of::<T>() - 1) != 0 {
+        return EmulationOutcome::MisalignedAddress { addr, access };
+    }
+
+    // an AMO trapping is a trap like any other as far as an outstanding reservation is concerned,
+    // and its store breaks the reservations other harts hold on the bytes it covers
+    #[cfg(feature = "zaamo")]
+    if !matches!(insn, AtomicInsn::Lr(_) | AtomicInsn::Sc(_)) {
+        reservation::on_trap();
+        reservation::stored(addr, core::mem::size_of::<T>());
+    }
+
+    let ordering = insn.operands().ordering();
+    fence(ordering, true);
+    let result = execute::<T, R, M>(insn, frame, bus);
+    fence(ordering, false);
+
+    match result {
+        Ok(()) => EmulationOutcome::Emulated {
+            next_pc: pc.wrapping_add(INSN_LEN),
+        },
+        Err(BusError) => EmulationOutcome::AccessFault { addr, access },
+    }
+}
+
+/// Performs the accesses of `insn` and writes its result, registers are only written once every
+/// access succeeded.
+#[inline(always)]
+fn execute<T: Value, R: RegisterFile, M: MemoryBus>(
+    insn: AtomicInsn,
+    frame: &mut R,
+    bus: &mut M,
+) -> Result<(), BusError> {
+    let Operands { rd, rs1, rs2, .. } = *insn.operands();
+
+    match insn {
+        #[cfg(feature = "zalrsc")]
+        AtomicInsn::Lr(_) => {
+            let tmp = frame.read(rs1);
+            let value = T::load(bus, tmp)?;
+            reservation::reserve(tmp, core::mem::size_of::<T>());
+            frame.write(rd, value.to_reg());
+        }
+        #[cfg(feature = "zalrsc")]
+        AtomicInsn::Sc(_) => {
+            let tmp = frame.read(rs1);
+            if reservation::take(tmp) {
+                reservation::stored(tmp, core::mem::size_of::<T>());
+                T::from_reg(frame.read(rs2)).store(bus, tmp)?;
+                frame.write(rd, 0);
             } else {
-                *(S_LR_ADDR as *mut _) = frame[rs2];
-                frame[rd] = 0;
-                S_LR_ADDR = 0;
+                frame.write(rd, 1);
             }
         }
-        0b00001 => {
-            /* AMOSWAP */
-            amo!(frame, rs1, rs2, rd, |_, b| b);
+        #[cfg(feature = "zaamo")]
+        AtomicInsn::AmoSwap(_) => {
+            amo!(bus, frame, rs1, rs2, rd, T, |_, b| b);
+        }
+        #[cfg(feature = "zaamo")]
+        AtomicInsn::AmoAdd(_) => {
+            amo!(bus, frame, rs1, rs2, rd, T, |a: T, b| a.wrapping_add(b));
         }
-        0b00000 => {
-            /* AMOADD */
-            amo!(frame, rs1, rs2, rd, |a, b| a + b);
+        #[cfg(feature = "zaamo")]
+        AtomicInsn::AmoXor(_) => {
+            amo!(bus, frame, rs1, rs2, rd, T, |a, b| a ^ b);
         }
-        0b00100 => {
-            /* AMOXOR */
-            amo!(frame, rs1, rs2, rd, |a, b| a ^ b);
+        #[cfg(feature = "zaamo")]
+        AtomicInsn::AmoAnd(_) => {
+            amo!(bus, frame, rs1, rs2, rd, T, |a, b| a & b);
         }
-        0b01100 => {
-            /* AMOAND */
-            amo!(frame, rs1, rs2, rd, |a, b| a & b);
+        #[cfg(feature = "zaamo")]
+        AtomicInsn::AmoOr(_) => {
+            amo!(bus, frame, rs1, rs2, rd, T, |a, b| a | b);
         }
-        0b01000 => {
-            /* AMOOR */
-            amo!(frame, rs1, rs2, rd, |a, b| a | b);
+        #[cfg(feature = "zaamo")]
+        AtomicInsn::AmoMin(_) => {
+            amo!(bus, frame, rs1, rs2, rd, T, |a: T, b| a.signed_min(b));
         }
-        0b10000 => {
-            /* AMOMIN */
-            amo!(frame, rs1, rs2, rd, |a, b| (a as isize).min(b as isize));
+        #[cfg(feature = "zaamo")]
+        AtomicInsn::AmoMax(_) => {
+            amo!(bus, frame, rs1, rs2, rd, T, |a: T, b| a.signed_max(b));
         }
-        0b10100 => {
-            /* AMOMAX */
-            amo!(frame, rs1, rs2, rd, |a, b| (a as isize).max(b as isize));
+        #[cfg(feature = "zaamo")]
+        AtomicInsn::AmoMinu(_) => {
+            amo!(bus, frame, rs1, rs2, rd, T, |a: T, b| a.min(b));
         }
-        0b11000 => {
-            /* AMOMINU */
-            amo!(frame, rs1, rs2, rd, |a: usize, b| a.min(b));
+        #[cfg(feature = "zaamo")]
+        AtomicInsn::AmoMaxu(_) => {
+            amo!(bus, frame, rs1, rs2, rd, T, |a: T, b| a.max(b));
         }
-        0b11100 => {
-            /* AMOMAXU */
-            amo!(frame, rs1, rs2, rd, |a: usize, b| a.max(b));
+        #[cfg(feature = "zaamo")]
+        AtomicInsn::AmoCas(_) => {
+            let tmp = frame.read(rs1);
+            let expected: T = read_cas(frame, rd);
+            let new: T = read_cas(frame, rs2);
+            let value = T::load(bus, tmp)?;
+            if value == expected {
+                new.store(bus, tmp)?;
+            }
+            write_cas(frame, rd, value);
         }
-        _ => return false,
+        // groups left out are reported as not atomic by `atomic_emulation_with_bus`
+        #[cfg(not(all(feature = "zalrsc", feature = "zaamo")))]
+        _ => {}
     }
 
-    true
+    Ok(())
 }
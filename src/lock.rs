@@ -0,0 +1,183 @@
+//! Mutual exclusion between harts emulating atomics at the same time.
+//!
+//! An emulated AMO is a plain load followed by a plain store, so two harts trapping on the same
+//! word would both read the old value and one update would be lost. On multi-hart chips a lock
+//! shared by all harts must be registered with [`set_emulation_lock`], the emulator holds it
+//! around every emulated instruction.
+//!
+//! The lock only orders emulated instructions against each other, ordinary stores from code that
+//! does not trap can still race with an emulated read-modify-write.
+//!
+//! Implementations must not use read-modify-write atomics themselves: those would trap back into
+//! the emulator.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::hart::{hart_id, HARTS};
+use crate::EmulationOutcome;
+
+static mut LOCK: &'static dyn EmulationLock = &NoLock;
+
+/// A lock shared by every hart running the emulator.
+pub trait EmulationLock: Sync {
+    /// Blocks until the current hart holds the lock.
+    ///
+    /// Returns false, without blocking, if the lock cannot serve the current hart. The instruction
+    /// is then not emulated and reported as [`EmulationOutcome::NotAtomic`].
+    fn acquire(&self) -> bool;
+    /// Releases the lock held by the current hart.
+    fn release(&self);
+}
+
+/// Registers the lock held around every emulated instruction, [`NoLock`] by default.
+///
+/// # Safety
+///
+/// Must not be called while another hart may be emulating an instruction, typically it is called
+/// once during start up before the other harts are released.
+pub unsafe fn set_emulation_lock(lock: &'static dyn EmulationLock) {
+    LOCK = lock;
+}
+
+/// Runs `f` with the registered lock held.
+#[inline(always)]
+pub(crate) fn locked(f: impl FnOnce() -> EmulationOutcome) -> EmulationOutcome {
+    // SAFETY: only written by `set_emulation_lock`, which may not race with emulation.
+    let lock = unsafe { LOCK };
+    if !lock.acquire() {
+        return EmulationOutcome::NotAtomic;
+    }
+    let result = f();
+    lock.release();
+    result
+}
+
+/// A lock that does nothing, for single hart systems.
+///
+/// Traps are taken with interrupts disabled, so on a single hart nothing can interleave with an
+/// emulated instruction.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NoLock;
+
+impl EmulationLock for NoLock {
+    #[inline(always)]
+    fn acquire(&self) -> bool {
+        true
+    }
+
+    #[inline(always)]
+    fn release(&self) {}
+}
+
+/// A lock backed by a hardware mutex or semaphore peripheral.
+///
+/// `try_lock` attempts to take the hardware lock and returns true on success, `unlock` releases
+/// it. Both are usually a single register access.
+#[derive(Debug, Clone, Copy)]
+pub struct HardwareMutex {
+    try_lock: fn() -> bool,
+    unlock: fn(),
+}
+
+impl HardwareMutex {
+    pub const fn new(try_lock: fn() -> bool, unlock: fn()) -> Self {
+        Self { try_lock, unlock }
+    }
+}
+
+impl EmulationLock for HardwareMutex {
+    #[inline]
+    fn acquire(&self) -> bool {
+        while !(self.try_lock)() {
+            core::hint::spin_loop();
+        }
+        true
+    }
+
+    #[inline]
+    fn release(&self) {
+        (self.unlock)()
+    }
+}
+
+/// Lamport's bakery lock for up to `N` harts, built from loads and stores only.
+///
+/// Harts whose id, as returned by [`hart_id`], is `N` or above cannot take the lock, their
+/// atomic instructions are left to the exception handler.
+///
+/// `N` may not exceed [`HARTS`]: in the default single hart configuration every hart has id 0 and
+/// would share one ticket, so the `harts-2`, `harts-4` or `harts-8` feature covering `N` has to be
+/// enabled for the lock to tell harts apart.
+///
+/// ```compile_fail
+/// use riscv_atomic_emulation_trap::lock::BakeryLock;
+///
+/// // more harts than any `harts-N` feature provides
+/// static LOCK: BakeryLock<16> = BakeryLock::new();
+/// ```
+#[derive(Debug)]
+pub struct BakeryLock<const N: usize> {
+    choosing: [AtomicBool; N],
+    tickets: [AtomicUsize; N],
+}
+
+impl<const N: usize> BakeryLock<N> {
+    pub const fn new() -> Self {
+        const {
+            assert!(
+                N <= HARTS,
+                "`BakeryLock<N>` needs a `harts-N` feature of at least N harts"
+            )
+        };
+        Self {
+            choosing: [const { AtomicBool::new(false) }; N],
+            tickets: [const { AtomicUsize::new(0) }; N],
+        }
+    }
+}
+
+impl<const N: usize> Default for BakeryLock<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> EmulationLock for BakeryLock<N> {
+    fn acquire(&self) -> bool {
+        let hart = hart_id();
+        let (Some(choosing), Some(own)) = (self.choosing.get(hart), self.tickets.get(hart)) else {
+            return false;
+        };
+
+        choosing.store(true, Ordering::SeqCst);
+        let ticket = 1 + self
+            .tickets
+            .iter()
+            .map(|t| t.load(Ordering::SeqCst))
+            .max()
+            .unwrap_or(0);
+        own.store(ticket, Ordering::SeqCst);
+        choosing.store(false, Ordering::SeqCst);
+
+        for other in (0..N).filter(|&other| other != hart) {
+            while self.choosing[other].load(Ordering::SeqCst) {
+                core::hint::spin_loop();
+            }
+            loop {
+                let theirs = self.tickets[other].load(Ordering::SeqCst);
+                if theirs == 0 || (ticket, hart) < (theirs, other) {
+                    break;
+                }
+                core::hint::spin_loop();
+            }
+        }
+        true
+    }
+
+    fn release(&self) {
+        // only reached once `acquire` succeeded, so the hart is in range
+        if let Some(own) = self.tickets.get(hart_id()) {
+            own.store(0, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,6 @@
+//! Machine mode trap helpers.
+//!
+//! A machine mode trap handler calls [`emulate_trap`] with the registers it saved and resumes
+//! with `mret`.
+
+trap_helpers!("mepc", "mcause", "mtval");
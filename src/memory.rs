@@ -0,0 +1,235 @@
+//! Memory accesses performed by the emulator.
+//!
+//! The emulator reaches memory through a [`MemoryBus`]. On targets it is [`RawMemory`], which
+//! dereferences the addresses found in the trap frame. Simulators and host tests can emulate
+//! instructions against their own address space instead, for example a `SparseMemory` with the
+//! `alloc` feature.
+
+use crate::region;
+
+/// An access the bus could not perform, reported as [`EmulationOutcome::AccessFault`].
+///
+/// [`EmulationOutcome::AccessFault`]: crate::EmulationOutcome::AccessFault
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusError;
+
+/// Loads and stores of the widths used by atomic instructions.
+///
+/// Addresses are naturally aligned for the access width, the emulator reports misaligned ones
+/// before reaching the bus. Values are in the target's byte order.
+pub trait MemoryBus {
+    fn load8(&mut self, addr: usize) -> Result<u8, BusError>;
+    fn load16(&mut self, addr: usize) -> Result<u16, BusError>;
+    fn load32(&mut self, addr: usize) -> Result<u32, BusError>;
+    fn load64(&mut self, addr: usize) -> Result<u64, BusError>;
+    fn store8(&mut self, addr: usize, value: u8) -> Result<(), BusError>;
+    fn store16(&mut self, addr: usize, value: u16) -> Result<(), BusError>;
+    fn store32(&mut self, addr: usize, value: u32) -> Result<(), BusError>;
+    fn store64(&mut self, addr: usize, value: u64) -> Result<(), BusError>;
+}
+
+/// The memory of the running program, accessed through raw pointers.
+///
+/// Addresses outside of the [allowed regions](crate::region) are refused, MMIO regions are
+/// accessed with volatile reads and writes.
+#[derive(Debug)]
+pub struct RawMemory(());
+
+impl RawMemory {
+    /// # Safety
+    ///
+    /// Every address the bus is handed must be valid for reads and writes of the access width,
+    /// which holds for the addresses of atomic instructions that trapped on the current hart.
+    pub const unsafe fn new() -> Self {
+        Self(())
+    }
+}
+
+macro_rules! raw_access {
+    ($load:ident, $store:ident, $ty:ty) => {
+        #[inline(always)]
+        fn $load(&mut self, addr: usize) -> Result<$ty, BusError> {
+            if !region::is_allowed(addr, core::mem::size_of::<$ty>()) {
+                return Err(BusError);
+            }
+            let ptr = addr as *const $ty;
+            // SAFETY: guaranteed by the caller of `RawMemory::new`.
+            Ok(unsafe {
+                if region::is_mmio(addr, core::mem::size_of::<$ty>()) {
+                    ptr.read_volatile()
+                } else {
+                    *ptr
+                }
+            })
+        }
+
+        #[inline(always)]
+        fn $store(&mut self, addr: usize, value: $ty) -> Result<(), BusError> {
+            if !region::is_allowed(addr, core::mem::size_of::<$ty>()) {
+                return Err(BusError);
+            }
+            let ptr = addr as *mut $ty;
+            // SAFETY: guaranteed by the caller of `RawMemory::new`.
+            unsafe {
+                if region::is_mmio(addr, core::mem::size_of::<$ty>()) {
+                    ptr.write_volatile(value)
+                } else {
+                    *ptr = value
+                }
+            }
+            Ok(())
+        }
+    };
+}
+
+impl MemoryBus for RawMemory {
+    raw_access!(load8, store8, u8);
+    raw_access!(load16, store16, u16);
+    raw_access!(load32, store32, u32);
+    raw_access!(load64, store64, u64);
+}
+
+/// A value the emulator moves over the bus.
+pub(crate) trait Word: Sized {
+    fn load<M: MemoryBus>(bus: &mut M, addr: usize) -> Result<Self, BusError>;
+    fn store<M: MemoryBus>(self, bus: &mut M, addr: usize) -> Result<(), BusError>;
+}
+
+macro_rules! impl_word {
+    ($ty:ty, $load:ident, $store:ident) => {
+        impl Word for $ty {
+            #[inline(always)]
+            fn load<M: MemoryBus>(bus: &mut M, addr: usize) -> Result<Self, BusError> {
+                bus.$load(addr)
+            }
+
+            #[inline(always)]
+            fn store<M: MemoryBus>(self, bus: &mut M, addr: usize) -> Result<(), BusError> {
+                bus.$store(addr, self)
+            }
+        }
+    };
+}
+
+impl_word!(u8, load8, store8);
+impl_word!(u16, load16, store16);
+impl_word!(u32, load32, store32);
+impl_word!(u64, load64, store64);
+
+/// `AMOCAS.Q` accesses, as two doublewords with the low one first in memory.
+impl Word for u128 {
+    #[inline(always)]
+    fn load<M: MemoryBus>(bus: &mut M, addr: usize) -> Result<Self, BusError> {
+        let lo = bus.load64(addr)?;
+        let hi = bus.load64(addr.wrapping_add(8))?;
+        Ok((hi as u128) << 64 | lo as u128)
+    }
+
+    #[inline(always)]
+    fn store<M: MemoryBus>(self, bus: &mut M, addr: usize) -> Result<(), BusError> {
+        bus.store64(addr, self as u64)?;
+        bus.store64(addr.wrapping_add(8), (self >> 64) as u64)
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub use sparse::SparseMemory;
+
+#[cfg(feature = "alloc")]
+mod sparse {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    use super::{BusError, MemoryBus};
+
+    const PAGE_SIZE: usize = 4096;
+
+    /// A little endian address space made of zero initialised pages, mapped on demand.
+    ///
+    /// Accesses to unmapped pages fail with [`BusError`].
+    #[derive(Debug, Default, Clone)]
+    pub struct SparseMemory {
+        /// Mapped pages sorted by address.
+        pages: Vec<(usize, Box<[u8; PAGE_SIZE]>)>,
+    }
+
+    impl SparseMemory {
+        /// Creates an address space with nothing mapped.
+        pub const fn new() -> Self {
+            Self { pages: Vec::new() }
+        }
+
+        /// Maps the pages covering `len` bytes from `addr`, pages mapped already are kept.
+        pub fn map(&mut self, addr: usize, len: usize) {
+            if len == 0 {
+                return;
+            }
+            let first = addr & !(PAGE_SIZE - 1);
+            let last = (addr + (len - 1)) & !(PAGE_SIZE - 1);
+            for page in (first..=last).step_by(PAGE_SIZE) {
+                if let Err(i) = self.find(page) {
+                    self.pages.insert(i, (page, Box::new([0; PAGE_SIZE])));
+                }
+            }
+        }
+
+        /// Returns true if the byte at `addr` is mapped.
+        pub fn is_mapped(&self, addr: usize) -> bool {
+            self.find(addr & !(PAGE_SIZE - 1)).is_ok()
+        }
+
+        /// Copies `buf.len()` bytes from `addr` into `buf`.
+        pub fn read(&self, addr: usize, buf: &mut [u8]) -> Result<(), BusError> {
+            for (i, byte) in buf.iter_mut().enumerate() {
+                *byte = *self.byte(addr.wrapping_add(i))?;
+            }
+            Ok(())
+        }
+
+        /// Copies `buf` to `addr`, nothing is written unless every byte is mapped.
+        pub fn write(&mut self, addr: usize, buf: &[u8]) -> Result<(), BusError> {
+            if !(0..buf.len()).all(|i| self.is_mapped(addr.wrapping_add(i))) {
+                return Err(BusError);
+            }
+            for (i, byte) in buf.iter().enumerate() {
+                *self.byte_mut(addr.wrapping_add(i))? = *byte;
+            }
+            Ok(())
+        }
+
+        fn find(&self, page: usize) -> Result<usize, usize> {
+            self.pages.binary_search_by_key(&page, |(base, _)| *base)
+        }
+
+        fn byte(&self, addr: usize) -> Result<&u8, BusError> {
+            let i = self.find(addr & !(PAGE_SIZE - 1)).map_err(|_| BusError)?;
+            Ok(&self.pages[i].1[addr & (PAGE_SIZE - 1)])
+        }
+
+        fn byte_mut(&mut self, addr: usize) -> Result<&mut u8, BusError> {
+            let i = self.find(addr & !(PAGE_SIZE - 1)).map_err(|_| BusError)?;
+            Ok(&mut self.pages[i].1[addr & (PAGE_SIZE - 1)])
+        }
+    }
+
+    macro_rules! sparse_access {
+        ($load:ident, $store:ident, $ty:ty) => {
+            fn $load(&mut self, addr: usize) -> Result<$ty, BusError> {
+                let mut bytes = [0; core::mem::size_of::<$ty>()];
+                self.read(addr, &mut bytes)?;
+                Ok(<$ty>::from_le_bytes(bytes))
+            }
+
+            fn $store(&mut self, addr: usize, value: $ty) -> Result<(), BusError> {
+                self.write(addr, &value.to_le_bytes())
+            }
+        };
+    }
+
+    impl MemoryBus for SparseMemory {
+        sparse_access!(load8, store8, u8);
+        sparse_access!(load16, store16, u16);
+        sparse_access!(load32, store32, u32);
+        sparse_access!(load64, store64, u64);
+    }
+}
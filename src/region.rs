@@ -0,0 +1,120 @@
+//! Address ranges emulated instructions may access.
+//!
+//! The emulator dereferences the address found in `rs1` with the privileges of the trap handler.
+//! A wild pointer used in an atomic would silently write wherever it points, flash mapped
+//! registers or the trap stack included. Registering the ranges atomics are expected to target
+//! with [`set_allowed_regions`] makes any other address fault instead, as
+//! [`EmulationOutcome::AccessFault`](crate::EmulationOutcome::AccessFault).
+//!
+//! Peripheral registers accessed with atomics, for example `AtomicU32::fetch_or` on a register
+//! block, are declared with [`set_mmio_regions`]. There, emulated instructions perform exactly one
+//! volatile read and at most one volatile write of the instruction's width, like the bus
+//! transactions of an AMO on hardware. MMIO regions are allowed whether or not they are part of
+//! the allowed regions.
+//!
+//! The allowed regions must cover every atomic, statics first. With `riscv-rt`'s linker script,
+//! `static` atomics live in `.data` or `.bss`, `_sdata.._ebss`, while atomics on the heap and the
+//! stacks lie in `_sheap.._stack_start`. The two are separate regions since `REGION_DATA` and
+//! `REGION_STACK` may be mapped to different memories.
+//!
+//! # Configuration
+//!
+//! The regions are kept in plain statics, read by the emulator without synchronisation since it
+//! cannot use read-modify-write atomics itself. They are registered before any other hart may
+//! emulate an instruction, typically once during start up before the other harts are released,
+//! and left alone afterwards.
+//!
+//! ```no_run
+//! use core::ptr::addr_of;
+//! use riscv_atomic_emulation_trap::region::{set_allowed_regions, Region};
+//!
+//! extern "C" {
+//!     static _sdata: u8;
+//!     static _ebss: u8;
+//!     static _sheap: u8;
+//!     static _stack_start: u8;
+//! }
+//!
+//! static RAM: [Region; 2] = [
+//!     Region::new(addr_of!(_sdata), addr_of!(_ebss)),
+//!     Region::new(addr_of!(_sheap), addr_of!(_stack_start)),
+//! ];
+//!
+//! unsafe { set_allowed_regions(&RAM) };
+//! ```
+
+/// A half-open range of addresses, `start..end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    start: *const u8,
+    end: *const u8,
+}
+
+// SAFETY: the pointers are only compared, never dereferenced.
+unsafe impl Sync for Region {}
+
+impl Region {
+    /// Creates the region from `start` up to, but excluding, `end`.
+    ///
+    /// Taking pointers lets tables be built in statics from linker symbols.
+    pub const fn new(start: *const u8, end: *const u8) -> Self {
+        Self { start, end }
+    }
+
+    /// Returns true if the `len` bytes from `addr` lie within the region.
+    #[inline]
+    pub fn contains(&self, addr: usize, len: usize) -> bool {
+        match addr.checked_add(len) {
+            Some(last) => self.start as usize <= addr && last <= self.end as usize,
+            None => false,
+        }
+    }
+}
+
+static mut ALLOWED: Option<&'static [Region]> = None;
+static mut MMIO: &[Region] = &[];
+
+/// Restricts emulated accesses to `regions`, every address is allowed by default.
+///
+/// # Safety
+///
+/// Must follow the [configuration](self#configuration) rules.
+pub unsafe fn set_allowed_regions(regions: &'static [Region]) {
+    ALLOWED = Some(regions);
+}
+
+/// Declares the peripheral register ranges, accessed with volatile reads and writes.
+///
+/// # Safety
+///
+/// Must follow the [configuration](self#configuration) rules.
+pub unsafe fn set_mmio_regions(regions: &'static [Region]) {
+    MMIO = regions;
+}
+
+/// The allowed and MMIO regions.
+#[inline(always)]
+fn registered() -> (Option<&'static [Region]>, &'static [Region]) {
+    // SAFETY: only written during configuration, which may not race with emulation.
+    unsafe { (ALLOWED, MMIO) }
+}
+
+/// Returns true if the emulator may access the `len` bytes from `addr`.
+#[inline]
+pub fn is_allowed(addr: usize, len: usize) -> bool {
+    match registered().0 {
+        Some(regions) => {
+            regions.iter().any(|region| region.contains(addr, len)) || is_mmio(addr, len)
+        }
+        None => true,
+    }
+}
+
+/// Returns true if the `len` bytes from `addr` are peripheral registers.
+#[inline]
+pub fn is_mmio(addr: usize, len: usize) -> bool {
+    registered()
+        .1
+        .iter()
+        .any(|region| region.contains(addr, len))
+}
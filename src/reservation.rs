@@ -0,0 +1,175 @@
+//! LR/SC reservation tracking.
+//!
+//! An emulated `LR` registers a reservation on the bytes it loaded, which the next emulated `SC`
+//! consumes whether it succeeds or not. Emulated stores overlapping those bytes, whatever their
+//! width, break the reservation. Code running between the two without trapping is invisible to the
+//! emulator, so anything that should break the reservation, such as a context switch or an
+//! interrupt handler storing to the reserved address, has to be reported with
+//! [`invalidate_reservation`] or through the hooks selected by the [`ReservationPolicy`].
+//!
+//! Each hart holds its own reservation, for the [`HARTS`] harts configured. Harts with an id of
+//! [`HARTS`] or above have nowhere to keep one, their `LR` and `SC` are not emulated and reported
+//! as [`EmulationOutcome::NotAtomic`](crate::EmulationOutcome::NotAtomic) rather than failing every
+//! `SC`.
+//!
+//! Only plain atomic loads and stores are used here: a read-modify-write operation would itself
+//! trap into the emulator.
+
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+
+use crate::hart::{hart_id, HARTS};
+
+/// Address value meaning no reservation is held.
+const NONE: usize = 0;
+
+static RESERVATIONS: ReservationSet<HARTS> = ReservationSet::new();
+static POLICY: AtomicU8 = AtomicU8::new(ReservationPolicy::Manual as u8);
+
+/// The reservations of `N` harts.
+struct ReservationSet<const N: usize> {
+    addrs: [AtomicUsize; N],
+    /// Bytes covered by each reservation.
+    lens: [AtomicUsize; N],
+}
+
+impl<const N: usize> ReservationSet<N> {
+    const fn new() -> Self {
+        Self {
+            addrs: [const { AtomicUsize::new(NONE) }; N],
+            lens: [const { AtomicUsize::new(0) }; N],
+        }
+    }
+
+    #[cfg(feature = "zalrsc")]
+    #[inline(always)]
+    fn reserve(&self, hart: usize, addr: usize, len: usize) {
+        if let (Some(reservation), Some(reserved_len)) = (self.addrs.get(hart), self.lens.get(hart))
+        {
+            reserved_len.store(len, Ordering::Relaxed);
+            reservation.store(addr, Ordering::Relaxed);
+        }
+    }
+
+    #[cfg(feature = "zalrsc")]
+    #[inline(always)]
+    fn take(&self, hart: usize, addr: usize) -> bool {
+        match self.addrs.get(hart) {
+            Some(reservation) => {
+                let reserved = reservation.load(Ordering::Relaxed);
+                reservation.store(NONE, Ordering::Relaxed);
+                reserved != NONE && reserved == addr
+            }
+            None => false,
+        }
+    }
+
+    #[inline(always)]
+    fn invalidate(&self, hart: usize) {
+        if let Some(reservation) = self.addrs.get(hart) {
+            reservation.store(NONE, Ordering::Relaxed);
+        }
+    }
+
+    #[cfg(any(feature = "zalrsc", feature = "zaamo"))]
+    #[inline(always)]
+    fn invalidate_range(&self, addr: usize, len: usize) {
+        for (reservation, reserved_len) in self.addrs.iter().zip(&self.lens) {
+            let reserved = reservation.load(Ordering::Relaxed);
+            let reserved_end = reserved.saturating_add(reserved_len.load(Ordering::Relaxed));
+            if reserved != NONE && reserved < addr.saturating_add(len) && addr < reserved_end {
+                reservation.store(NONE, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Events, besides an `SC`, that break the reservation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ReservationPolicy {
+    /// Only [`invalidate_reservation`] breaks the reservation. This is the default.
+    Manual,
+    /// Every trap breaks the reservation: emulated AMOs, [`on_trap`] and [`on_interrupt`].
+    OnTrap,
+    /// Interrupts break the reservation, see [`on_interrupt`].
+    OnInterrupt,
+}
+
+/// Selects which events break the reservation.
+pub fn set_reservation_policy(policy: ReservationPolicy) {
+    POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Returns the active [`ReservationPolicy`].
+pub fn reservation_policy() -> ReservationPolicy {
+    match POLICY.load(Ordering::Relaxed) {
+        1 => ReservationPolicy::OnTrap,
+        2 => ReservationPolicy::OnInterrupt,
+        _ => ReservationPolicy::Manual,
+    }
+}
+
+/// Breaks the reservation of the current hart, its next `SC` will fail.
+///
+/// Context switchers should call this when switching tasks, as should any code storing to memory
+/// another context may hold a reservation on.
+#[inline]
+pub fn invalidate_reservation() {
+    RESERVATIONS.invalidate(hart_id());
+}
+
+/// Breaks the reservation of `hart`.
+#[inline]
+pub fn invalidate_hart_reservation(hart: usize) {
+    RESERVATIONS.invalidate(hart);
+}
+
+/// Hook for trap handlers, to be called on every trap that is not handled by the emulator.
+///
+/// Breaks the reservation of the current hart under [`ReservationPolicy::OnTrap`].
+#[inline]
+pub fn on_trap() {
+    if reservation_policy() == ReservationPolicy::OnTrap {
+        invalidate_reservation();
+    }
+}
+
+/// Hook for interrupt entry, to be called on every interrupt.
+///
+/// Breaks the reservation of the current hart under [`ReservationPolicy::OnTrap`] and
+/// [`ReservationPolicy::OnInterrupt`].
+#[inline]
+pub fn on_interrupt() {
+    if reservation_policy() != ReservationPolicy::Manual {
+        invalidate_reservation();
+    }
+}
+
+/// Returns true if the current hart can hold a reservation.
+#[cfg(feature = "zalrsc")]
+#[inline(always)]
+pub(crate) fn tracked() -> bool {
+    hart_id() < HARTS
+}
+
+/// Registers a reservation on the `len` bytes from `addr` for the current hart, replacing any
+/// previous one.
+#[cfg(feature = "zalrsc")]
+#[inline(always)]
+pub(crate) fn reserve(addr: usize, len: usize) {
+    RESERVATIONS.reserve(hart_id(), addr, len);
+}
+
+/// Consumes the reservation of the current hart, returns true if it was held on `addr`.
+#[cfg(feature = "zalrsc")]
+#[inline(always)]
+pub(crate) fn take(addr: usize) -> bool {
+    RESERVATIONS.take(hart_id(), addr)
+}
+
+/// Breaks the reservations any hart holds on the `len` bytes from `addr`, as a store to them does.
+#[cfg(any(feature = "zalrsc", feature = "zaamo"))]
+#[inline(always)]
+pub(crate) fn stored(addr: usize, len: usize) {
+    RESERVATIONS.invalidate_range(addr, len);
+}
@@ -0,0 +1,17 @@
+//! Supervisor mode trap helpers, enabled with the `supervisor` feature.
+//!
+//! For kernels running in S-mode under firmware that delegates illegal instruction traps, the
+//! kernel's trap handler calls [`emulate_trap`] with the registers it saved and resumes with
+//! `sret`. When the `riscv-rt` feature is enabled as well, the provided `_start_trap` switches to
+//! these CSRs and to `sret`, to be used together with `riscv-rt`'s `s-mode` feature.
+//!
+//! # Multi-hart kernels
+//!
+//! Supervisor mode cannot read `mhartid`, and `tp` is no substitute: on a trap from U-mode it
+//! holds the user's thread pointer. With a `harts-N` feature enabled, the kernel must therefore
+//! register where its hart id is kept with [`set_hart_id_source`](crate::hart::set_hart_id_source)
+//! before the emulator runs. Until then the hart is [unknown](crate::hart::UNKNOWN_HART): `LR`/`SC`
+//! and atomics needing a [`BakeryLock`](crate::lock::BakeryLock) are reported as
+//! [`NotAtomic`](crate::EmulationOutcome::NotAtomic). Single hart builds need no source.
+
+trap_helpers!("sepc", "scause", "stval");
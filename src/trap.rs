@@ -0,0 +1,75 @@
+//! Trap entry for `riscv-rt` applications, enabled with the `riscv-rt` feature.
+//!
+//! `_start_trap` replaces the default entry provided by `riscv-rt`. It saves `x0-x31` into a frame
+//! on the stack and emulates atomic instructions trapping as illegal instructions. Every other
+//! trap, including atomic instructions that turn out to be genuine faults, is passed on to
+//! `riscv-rt`'s `_start_trap_rust`, which dispatches it to the `ExceptionHandler` or the interrupt
+//! handlers as usual.
+//!
+//! The entry runs in machine mode, or in supervisor mode with the `supervisor` feature.
+//!
+//! The frame handed to `riscv-rt` follows its `TrapFrame` layout, saving the caller saved
+//! registers.
+
+use crate::frame::RiscvRtTrapFrame;
+#[cfg(not(feature = "supervisor"))]
+use crate::machine as mode;
+#[cfg(feature = "supervisor")]
+use crate::supervisor as mode;
+use crate::{reservation, EmulationOutcome, TrapFrame};
+
+#[cfg(target_feature = "e")]
+compile_error!("the riscv-rt trap entry does not support the reduced register file yet");
+
+macro_rules! trap_entry {
+    ($store:literal, $load:literal, $xlen:literal, $ret:literal) => {
+        core::arch::global_asm!(
+            ".section .trap, \"ax\"",
+            ".global _start_trap",
+            ".p2align 2",
+            "_start_trap:",
+            concat!("addi sp, sp, -32*", $xlen),
+            concat!($store, " x0, 0(sp)"),
+            concat!($store, " x1, 1*", $xlen, "(sp)"),
+            // the stack pointer at the time of the trap
+            concat!("addi x1, sp, 32*", $xlen),
+            concat!($store, " x1, 2*", $xlen, "(sp)"),
+            ".irp r, 3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+            concat!($store, " x\\r, \\r*", $xlen, "(sp)"),
+            ".endr",
+            "mv a0, sp",
+            "call _atomic_emulation_start_trap_rust",
+            concat!($load, " x1, 1*", $xlen, "(sp)"),
+            ".irp r, 3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+            concat!($load, " x\\r, \\r*", $xlen, "(sp)"),
+            ".endr",
+            // last, an emulated instruction may have written to sp
+            concat!($load, " x2, 2*", $xlen, "(sp)"),
+            $ret,
+        );
+    };
+}
+
+#[cfg(all(target_arch = "riscv32", not(feature = "supervisor")))]
+trap_entry!("sw", "lw", 4, "mret");
+#[cfg(all(target_arch = "riscv64", not(feature = "supervisor")))]
+trap_entry!("sd", "ld", 8, "mret");
+#[cfg(all(target_arch = "riscv32", feature = "supervisor"))]
+trap_entry!("sw", "lw", 4, "sret");
+#[cfg(all(target_arch = "riscv64", feature = "supervisor"))]
+trap_entry!("sd", "ld", 8, "sret");
+
+extern "C" {
+    fn _start_trap_rust(trap_frame: *const RiscvRtTrapFrame);
+}
+
+#[export_name = "_atomic_emulation_start_trap_rust"]
+unsafe extern "C" fn start_trap_rust(frame: &mut TrapFrame) {
+    match mode::emulate_trap(frame) {
+        Some(EmulationOutcome::Emulated { .. }) => return,
+        None if mode::is_interrupt() => reservation::on_interrupt(),
+        _ => reservation::on_trap(),
+    }
+
+    _start_trap_rust(&RiscvRtTrapFrame::from(&*frame));
+}
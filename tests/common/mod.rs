@@ -0,0 +1,150 @@
+//! Helpers for driving `atomic_emulation` on the host.
+#![allow(dead_code)]
+
+use std::cell::Cell;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use riscv_atomic_emulation_trap::hart::set_hart_id_source;
+use riscv_atomic_emulation_trap::{
+    atomic_emulation, EmulationOutcome, RegisterFile, PLATFORM_REGISTER_LEN,
+};
+
+pub type Frame = [usize; PLATFORM_REGISTER_LEN];
+
+pub const A0: usize = 10;
+pub const A1: usize = 11;
+pub const A2: usize = 12;
+pub const A3: usize = 13;
+pub const A4: usize = 14;
+
+pub const B: u32 = 0b000;
+pub const H: u32 = 0b001;
+pub const W: u32 = 0b010;
+pub const D: u32 = 0b011;
+pub const Q: u32 = 0b100;
+
+/// Widths the host can emulate.
+#[cfg(target_pointer_width = "64")]
+pub const WIDTHS: [(u32, usize); 2] = [(W, 4), (D, 8)];
+#[cfg(not(target_pointer_width = "64"))]
+pub const WIDTHS: [(u32, usize); 1] = [(W, 4)];
+
+/// `funct5` of every atomic operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Lr = 0b00010,
+    Sc = 0b00011,
+    Swap = 0b00001,
+    Add = 0b00000,
+    Xor = 0b00100,
+    And = 0b01100,
+    Or = 0b01000,
+    Min = 0b10000,
+    Max = 0b10100,
+    Minu = 0b11000,
+    Maxu = 0b11100,
+    Cas = 0b00101,
+}
+
+pub const AMOS: [Op; 9] = [
+    Op::Swap,
+    Op::Add,
+    Op::Xor,
+    Op::And,
+    Op::Or,
+    Op::Min,
+    Op::Max,
+    Op::Minu,
+    Op::Maxu,
+];
+
+/// Assembles an atomic instruction.
+pub fn encode(op: Op, funct3: u32, aq: bool, rl: bool, rd: usize, rs1: usize, rs2: usize) -> u32 {
+    (op as u32) << 27
+        | (aq as u32) << 26
+        | (rl as u32) << 25
+        | (rs2 as u32) << 20
+        | (rs1 as u32) << 15
+        | funct3 << 12
+        | (rd as u32) << 7
+        | 0b0101111
+}
+
+/// Serializes tests relying on the emulator's global reservation state.
+pub fn serial() -> MutexGuard<'static, ()> {
+    static SERIAL: Mutex<()> = Mutex::new(());
+    SERIAL.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+thread_local! {
+    static HART: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Makes the current thread run the emulator as `hart`.
+pub fn on_hart(hart: usize) {
+    set_hart_id_source(|| HART.with(Cell::get));
+    HART.with(|h| h.set(hart));
+}
+
+/// A frame where every register but `x0` holds a distinct, recognisable value.
+pub fn frame() -> Frame {
+    core::array::from_fn(|i| if i == 0 { 0 } else { 0x1000 + i })
+}
+
+/// Runs the emulator on `insn` as if it had trapped at its own address.
+pub fn outcome<R: RegisterFile>(insn: u32, frame: &mut R) -> EmulationOutcome {
+    unsafe { atomic_emulation(&insn as *const u32 as usize, frame) }
+}
+
+/// Runs the emulator on `insn`, returns true if it was emulated and the pc moved past it.
+pub fn emulate<R: RegisterFile>(insn: u32, frame: &mut R) -> bool {
+    let pc = &insn as *const u32 as usize;
+    match unsafe { atomic_emulation(pc, frame) } {
+        EmulationOutcome::Emulated { next_pc } => {
+            assert_eq!(next_pc, pc + 4);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Runs `amoadd.w a0, a1, (a2)` on a word holding 40 with `a1 = 2`, then checks that `a0` got
+/// the old value and memory the sum.
+pub fn assert_amoadd_40_plus_2<R: RegisterFile>(frame: &mut R) {
+    assert_amoadd_40_plus_2_with(frame, |insn, frame| assert!(emulate(insn, frame)));
+}
+
+/// [`assert_amoadd_40_plus_2`], with the instruction emulated by `run`.
+pub fn assert_amoadd_40_plus_2_with<R: RegisterFile>(frame: &mut R, run: impl FnOnce(u32, &mut R)) {
+    let mut mem = 40u32;
+    frame.write(A2, &mut mem as *mut u32 as usize);
+    frame.write(A1, 2);
+
+    run(encode(Op::Add, W, false, false, A0, A2, A1), frame);
+    assert_eq!(frame.read(A0), 40);
+    assert_eq!(mem, 42);
+}
+
+/// Golden model of an AMO of `bytes` width, returns the value written to `rd` and the new memory
+/// contents.
+pub fn reference(op: Op, bytes: usize, mem: u64, src: u64) -> (u64, u64) {
+    let bits = bytes as u32 * 8;
+    let mask = u64::MAX >> (64 - bits);
+    let (mem, src) = (mem & mask, src & mask);
+    let sext = |v: u64| ((v << (64 - bits)) as i64 >> (64 - bits)) as u64;
+
+    let new = match op {
+        Op::Swap => src,
+        Op::Add => mem.wrapping_add(src),
+        Op::Xor => mem ^ src,
+        Op::And => mem & src,
+        Op::Or => mem | src,
+        Op::Min => (sext(mem) as i64).min(sext(src) as i64) as u64,
+        Op::Max => (sext(mem) as i64).max(sext(src) as i64) as u64,
+        Op::Minu => mem.min(src),
+        Op::Maxu => mem.max(src),
+        Op::Lr | Op::Sc | Op::Cas => unreachable!("not modelled"),
+    };
+
+    (sext(mem), new & mask)
+}
@@ -0,0 +1,1205 @@
+//! Emulation of atomic instructions on an `x0-x31` frame.
+//!
+//! Every LR/SC/AMO encoding the host can emulate is checked register by register and byte by byte
+//! against the reference model, the modules below cover the rest of the emulator's behaviour.
+
+mod common;
+
+use common::*;
+use riscv_atomic_emulation_trap::EmulationOutcome;
+
+/// Surrounds the operand so out of bounds writes are caught.
+const GUARD: u64 = 0xa5a5_a5a5_a5a5_a5a5;
+
+#[cfg(any(feature = "zalrsc", feature = "zaamo"))]
+const VALUES: [u64; 9] = [
+    0,
+    1,
+    7,
+    0x7fff_ffff,
+    0x8000_0000,
+    0xffff_ffff,
+    0x8000_0000_0000_0000,
+    0xffff_ffff_ffff_ffff,
+    0x1234_5678_9abc_def0,
+];
+
+const ORDERINGS: [(bool, bool); 4] = [(false, false), (true, false), (false, true), (true, true)];
+
+/// `(rd, rs1, rs2)`, including destinations aliasing the sources.
+#[cfg(feature = "zaamo")]
+const REGISTERS: [(usize, usize, usize); 5] = [
+    (A0, A2, A1),
+    (A2, A2, A1),
+    (A1, A2, A1),
+    (31, 1, 5),
+    (A0, A2, A2),
+];
+
+fn mask(bytes: usize) -> u64 {
+    u64::MAX >> (64 - bytes * 8)
+}
+
+/// Memory with `value` in the low `bytes` of the middle doubleword.
+fn memory(bytes: usize, value: u64) -> Vec<u64> {
+    vec![GUARD, GUARD & !mask(bytes) | value & mask(bytes), GUARD]
+}
+
+#[cfg(feature = "zaamo")]
+#[allow(clippy::too_many_arguments)]
+fn check_amo(
+    op: Op,
+    funct3: u32,
+    bytes: usize,
+    aq: bool,
+    rl: bool,
+    regs: (usize, usize, usize),
+    a: u64,
+    b: u64,
+) {
+    let (rd, rs1, rs2) = regs;
+    let mut mem = memory(bytes, a);
+    let mut frame = frame();
+    frame[rs2] = b as usize;
+    frame[rs1] = &mut mem[1] as *mut u64 as usize;
+
+    let (loaded, new) = reference(op, bytes, a, frame[rs2] as u64);
+    let mut expected_frame = frame;
+    if rd != 0 {
+        expected_frame[rd] = loaded as usize;
+    }
+    let mut expected_mem = mem.clone();
+    expected_mem[1] = mem[1] & !mask(bytes) | new;
+
+    let insn = encode(op, funct3, aq, rl, rd, rs1, rs2);
+    assert!(emulate(insn, &mut frame), "{insn:#010x} was not emulated");
+    assert_eq!(frame, expected_frame, "{op:?} {insn:#010x} {a:#x} {b:#x}");
+    assert_eq!(mem, expected_mem, "{op:?} {insn:#010x} {a:#x} {b:#x}");
+}
+
+#[cfg(feature = "zaamo")]
+#[test]
+fn amo_matches_reference() {
+    for op in AMOS {
+        for (funct3, bytes) in WIDTHS {
+            for (aq, rl) in ORDERINGS {
+                for regs in REGISTERS {
+                    for a in VALUES {
+                        for b in VALUES {
+                            check_amo(op, funct3, bytes, aq, rl, regs, a, b);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "zalrsc")]
+#[test]
+fn lr_sc_matches_reference() {
+    let _serial = serial();
+
+    for (funct3, bytes) in WIDTHS {
+        for (aq, rl) in ORDERINGS {
+            for value in VALUES {
+                let lr = encode(Op::Lr, funct3, aq, rl, A0, A2, 0);
+                let sc = encode(Op::Sc, funct3, aq, rl, A3, A2, A1);
+                let (loaded, _) = reference(Op::Swap, bytes, value, 0);
+
+                let mut mem = memory(bytes, value);
+                let mut frame = frame();
+                frame[A2] = &mut mem[1] as *mut u64 as usize;
+                frame[A1] = !value as usize;
+
+                let mut expected_frame = frame;
+                expected_frame[A0] = loaded as usize;
+                let mut expected_mem = mem.clone();
+                assert!(emulate(lr, &mut frame));
+                assert_eq!(frame, expected_frame);
+                assert_eq!(mem, expected_mem);
+
+                // the reservation is held, the store goes through
+                expected_frame[A3] = 0;
+                expected_mem[1] = mem[1] & !mask(bytes) | !value & mask(bytes);
+                assert!(emulate(sc, &mut frame));
+                assert_eq!(frame, expected_frame);
+                assert_eq!(mem, expected_mem);
+
+                // and is consumed by it
+                expected_frame[A3] = 1;
+                frame[A1] = value as usize;
+                expected_frame[A1] = value as usize;
+                assert!(emulate(sc, &mut frame));
+                assert_eq!(frame, expected_frame);
+                assert_eq!(mem, expected_mem);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "zalrsc")]
+#[test]
+fn sc_fails_on_other_address() {
+    let _serial = serial();
+
+    for (funct3, bytes) in WIDTHS {
+        let mut mem = memory(bytes, 1);
+        let mut frame = frame();
+        frame[A2] = &mut mem[1] as *mut u64 as usize;
+        assert!(emulate(
+            encode(Op::Lr, funct3, false, false, A0, A2, 0),
+            &mut frame
+        ));
+
+        frame[A2] = &mut mem[0] as *mut u64 as usize;
+        let expected_mem = mem.clone();
+        assert!(emulate(
+            encode(Op::Sc, funct3, false, false, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(frame[A0], 1);
+        assert_eq!(mem, expected_mem);
+    }
+}
+
+#[test]
+fn rejects_non_atomic_and_reserved_encodings() {
+    let mut mem = memory(8, 1);
+    let mut frame = frame();
+    frame[A2] = &mut mem[1] as *mut u64 as usize;
+    let expected_frame = frame;
+    let expected_mem = mem.clone();
+
+    let amoadd = encode(Op::Add, W, false, false, A0, A2, A1);
+    let rejected = [
+        // addi a0, a0, 1
+        (0x0015_0513, EmulationOutcome::NotAtomic),
+        // reserved funct3
+        (amoadd | 0b111 << 12, EmulationOutcome::IllegalEncoding),
+        // reserved funct5
+        (amoadd | 0b11111 << 27, EmulationOutcome::IllegalEncoding),
+        // LR with rs2 != x0
+        (
+            encode(Op::Lr, W, false, false, A0, A2, A1),
+            EmulationOutcome::IllegalEncoding,
+        ),
+        #[cfg(not(target_pointer_width = "64"))]
+        (
+            encode(Op::Add, D, false, false, A0, A2, A1),
+            EmulationOutcome::IllegalEncoding,
+        ),
+    ];
+
+    for (insn, expected) in rejected {
+        assert_eq!(outcome(insn, &mut frame), expected, "{insn:#010x}");
+        assert_eq!(frame, expected_frame);
+        assert_eq!(mem, expected_mem);
+    }
+}
+
+#[test]
+fn ordering_follows_aq_rl() {
+    use core::sync::atomic::Ordering;
+    use riscv_atomic_emulation_trap::decode::decode;
+
+    let expected = [
+        Ordering::Relaxed,
+        Ordering::Acquire,
+        Ordering::Release,
+        Ordering::SeqCst,
+    ];
+    for ((aq, rl), ordering) in ORDERINGS.into_iter().zip(expected) {
+        let insn = decode(encode(Op::Swap, W, aq, rl, A0, A2, A1)).unwrap();
+        assert_eq!(insn.operands().ordering(), ordering);
+    }
+}
+
+/// Outcomes reported to the trap handler.
+mod outcome {
+    use super::*;
+
+    use riscv_atomic_emulation_trap::Access;
+
+    #[cfg(feature = "zaamo")]
+    #[test]
+    fn emulated_moves_pc_past_instruction() {
+        let mut mem = 0u32;
+        let mut frame = frame();
+        frame[A2] = &mut mem as *mut u32 as usize;
+
+        let insns = [encode(Op::Swap, W, false, false, A0, A2, A1)];
+        let pc = insns.as_ptr() as usize;
+        let outcome = unsafe { riscv_atomic_emulation_trap::atomic_emulation(pc, &mut frame) };
+
+        assert_eq!(outcome, EmulationOutcome::Emulated { next_pc: pc + 4 });
+        assert!(outcome.is_emulated());
+        assert_eq!(outcome.exception_code(), None);
+    }
+
+    #[test]
+    fn exception_codes() {
+        let addr = 0x8000_0002;
+        let cases = [
+            (EmulationOutcome::NotAtomic, None, 0),
+            (EmulationOutcome::IllegalEncoding, Some(2), 0),
+            (
+                EmulationOutcome::MisalignedAddress {
+                    addr,
+                    access: Access::Load,
+                },
+                Some(4),
+                addr,
+            ),
+            (
+                EmulationOutcome::AccessFault {
+                    addr,
+                    access: Access::Load,
+                },
+                Some(5),
+                addr,
+            ),
+            (
+                EmulationOutcome::MisalignedAddress {
+                    addr,
+                    access: Access::Store,
+                },
+                Some(6),
+                addr,
+            ),
+            (
+                EmulationOutcome::AccessFault {
+                    addr,
+                    access: Access::Store,
+                },
+                Some(7),
+                addr,
+            ),
+        ];
+
+        for (outcome, code, tval) in cases {
+            assert!(!outcome.is_emulated());
+            assert_eq!(outcome.exception_code(), code, "{outcome:?}");
+            assert_eq!(outcome.trap_value(), tval, "{outcome:?}");
+        }
+    }
+}
+
+/// The emulation lock with the hart id source of a target.
+#[cfg(feature = "zaamo")]
+mod lock {
+    use super::*;
+
+    use riscv_atomic_emulation_trap::hart::HARTS;
+    use riscv_atomic_emulation_trap::lock::{set_emulation_lock, BakeryLock, NoLock};
+
+    #[test]
+    fn bakery_lock_serves_default_hart_id() {
+        static LOCK: BakeryLock<HARTS> = BakeryLock::new();
+        let _serial = serial();
+
+        unsafe { set_emulation_lock(&LOCK) };
+        assert_amoadd_40_plus_2(&mut frame());
+        unsafe { set_emulation_lock(&NoLock) };
+    }
+}
+
+/// Fetching instructions from 16-bit aligned addresses, as found in RVC binaries.
+mod fetch {
+    use super::*;
+
+    use riscv_atomic_emulation_trap::decode::instruction_length;
+    use riscv_atomic_emulation_trap::{atomic_emulation, fetch_instruction};
+
+    #[test]
+    fn instruction_lengths() {
+        // c.nop
+        assert_eq!(instruction_length(0x0001), Some(2));
+        // amoadd.w
+        assert_eq!(
+            instruction_length(encode(Op::Add, W, false, false, A0, A2, A1) as u16),
+            Some(4)
+        );
+        assert_eq!(instruction_length(0b011111), Some(6));
+        assert_eq!(instruction_length(0b0111111), Some(8));
+        assert_eq!(instruction_length(0b000_0000_0111_1111), Some(10));
+        assert_eq!(instruction_length(0b110_0000_0111_1111), Some(22));
+        assert_eq!(instruction_length(0b111_0000_0111_1111), None);
+    }
+
+    #[cfg(feature = "zaamo")]
+    #[test]
+    fn fetches_instruction_on_halfword_boundary() {
+        assert_amoadd_40_plus_2_with(&mut frame(), |insn, frame| {
+            // c.nop followed by the AMO
+            let text = [0x0001, insn as u16, (insn >> 16) as u16];
+            let pc = &text[1] as *const u16 as usize;
+
+            assert_eq!(unsafe { fetch_instruction(pc) }, insn);
+            let outcome = unsafe { atomic_emulation(pc, frame) };
+            assert_eq!(outcome, EmulationOutcome::Emulated { next_pc: pc + 4 });
+        });
+    }
+
+    #[test]
+    fn compressed_instruction_is_not_atomic() {
+        // a lone c.nop, reading past it would be out of bounds
+        let text = [0x0001u16];
+        let pc = text.as_ptr() as usize;
+
+        assert_eq!(unsafe { fetch_instruction(pc) }, 0x0001);
+        let mut frame = frame();
+        assert_eq!(
+            unsafe { atomic_emulation(pc, &mut frame) },
+            EmulationOutcome::NotAtomic
+        );
+    }
+}
+
+/// Emulating an instruction handed over by the trap handler instead of fetched from the pc.
+mod insn {
+    use super::*;
+
+    use riscv_atomic_emulation_trap::trapped_instruction;
+
+    #[cfg(feature = "zaamo")]
+    #[test]
+    fn emulates_given_instruction() {
+        use riscv_atomic_emulation_trap::atomic_emulation_with_insn;
+
+        assert_amoadd_40_plus_2_with(&mut frame(), |insn, frame| {
+            // the pc is never dereferenced
+            let pc = 0x4200_0000;
+            let outcome = unsafe { atomic_emulation_with_insn(pc, insn, frame) };
+            assert_eq!(outcome, EmulationOutcome::Emulated { next_pc: pc + 4 });
+        });
+    }
+
+    #[test]
+    fn trapped_instruction_prefers_tval() {
+        let insn = encode(Op::Swap, W, false, false, A0, A2, A1);
+        let other = encode(Op::Add, W, false, false, A0, A2, A1);
+        let pc = &insn as *const u32 as usize;
+
+        assert_eq!(unsafe { trapped_instruction(pc, other as usize) }, other);
+        assert_eq!(unsafe { trapped_instruction(pc, 0) }, insn);
+    }
+}
+
+/// Instructions with `x0` as destination must leave the saved `x0` slot untouched.
+#[cfg(any(feature = "zalrsc", feature = "zaamo"))]
+mod x0 {
+    use super::*;
+
+    const ZERO: usize = 0;
+
+    #[cfg(feature = "zaamo")]
+    #[test]
+    fn amo_discards_x0_destination() {
+        for op in AMOS {
+            let mut mem = 0x8000_0005u32;
+            let mut frame: Frame = [0; 32];
+            frame[A2] = &mut mem as *mut u32 as usize;
+            frame[A1] = 3;
+
+            assert!(emulate(
+                encode(op, W, false, false, ZERO, A2, A1),
+                &mut frame
+            ));
+
+            let (_, new) = reference(op, 4, 0x8000_0005, 3);
+            assert_eq!(frame[ZERO], 0, "{op:?} wrote to x0");
+            assert_eq!(mem as u64, new, "{op:?} did not update memory");
+        }
+    }
+
+    #[cfg(feature = "zalrsc")]
+    #[test]
+    fn lr_sc_discard_x0_destination() {
+        let mut mem = 0x8000_0005u32;
+        let mut frame: Frame = [0; 32];
+        frame[A2] = &mut mem as *mut u32 as usize;
+        frame[A1] = 3;
+
+        assert!(emulate(
+            encode(Op::Lr, W, false, false, ZERO, A2, 0),
+            &mut frame
+        ));
+        assert_eq!(frame[ZERO], 0);
+
+        // a successful SC writes 0, a failing one 1
+        assert!(emulate(
+            encode(Op::Sc, W, false, false, ZERO, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(frame[ZERO], 0);
+        assert_eq!(mem, 3);
+        assert!(emulate(
+            encode(Op::Sc, W, false, false, ZERO, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(frame[ZERO], 0);
+    }
+}
+
+/// `AMOADD` wraps on overflow instead of panicking, whatever the `overflow-checks` setting.
+#[cfg(feature = "zaamo")]
+mod wrapping {
+    use super::*;
+
+    #[test]
+    fn amoadd_word_wraps() {
+        for (a, b) in [
+            (u32::MAX, 1),
+            (0x8000_0000, 0x8000_0000),
+            (0x7fff_ffff, 1),
+            (u32::MAX, u32::MAX),
+        ] {
+            let mut mem = a;
+            let mut frame: Frame = [0; 32];
+            frame[A2] = &mut mem as *mut u32 as usize;
+            frame[A1] = b as usize;
+
+            assert!(emulate(
+                encode(Op::Add, W, false, false, A0, A2, A1),
+                &mut frame
+            ));
+            assert_eq!(mem, a.wrapping_add(b), "{a:#x} + {b:#x}");
+            assert_eq!(frame[A0], a as i32 as isize as usize);
+        }
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn amoadd_double_wraps() {
+        for (a, b) in [(u64::MAX, 1), (1 << 63, 1 << 63), (i64::MAX as u64, 1)] {
+            let mut mem = a;
+            let mut frame: Frame = [0; 32];
+            frame[A2] = &mut mem as *mut u64 as usize;
+            frame[A1] = b as usize;
+
+            assert!(emulate(
+                encode(Op::Add, D, false, false, A0, A2, A1),
+                &mut frame
+            ));
+            assert_eq!(mem, a.wrapping_add(b), "{a:#x} + {b:#x}");
+            assert_eq!(frame[A0], a as usize);
+        }
+    }
+}
+
+/// `.w` and `.d` emulation on a 64-bit target, checked against the reference model.
+#[cfg(all(target_pointer_width = "64", feature = "zalrsc", feature = "zaamo"))]
+mod rv64 {
+    use super::*;
+
+    const SENTINEL: u32 = 0x5a5a_5a5a;
+
+    const WORDS: [(u32, u32); 6] = [
+        (5, 7),
+        (0x8000_0000, 3),
+        (0x7fff_fff0, 0x0000_000f),
+        (0xffff_fff0, 0x0000_000e),
+        (0, 0x8000_0001),
+        (0x1234_5678, 0x0edc_0000),
+    ];
+
+    const DOUBLES: [(u64, u64); 5] = [
+        (5, 7),
+        (0x8000_0000_0000_0000, 3),
+        (0x0000_0001_0000_0000, 0xffff_ffff),
+        (0xffff_ffff_0000_0000, 0x0000_0000_ffff_ffff),
+        (0x1234_5678_9abc_def0, 0x0fed_cba9_8765_4321),
+    ];
+
+    #[test]
+    fn amo_word_matches_reference() {
+        for op in AMOS {
+            for (a, b) in WORDS {
+                let mut mem = [a, SENTINEL];
+                let mut frame: Frame = [0; 32];
+                frame[A2] = mem.as_mut_ptr() as usize;
+                // the upper half of rs2 must be ignored by `.w` operations
+                frame[A1] = 0xdead_beef_0000_0000 | b as usize;
+
+                assert!(emulate(encode(op, W, false, false, A0, A2, A1), &mut frame));
+
+                let (rd, new) = reference(op, 4, a as u64, b as u64);
+                assert_eq!(frame[A0] as u64, rd, "{op:?} {a:#x} {b:#x}");
+                assert_eq!(mem[0] as u64, new, "{op:?} {a:#x} {b:#x}");
+                assert_eq!(mem[1], SENTINEL, "{op:?} clobbered the neighbouring word");
+            }
+        }
+    }
+
+    #[test]
+    fn amo_double_matches_reference() {
+        for op in AMOS {
+            for (a, b) in DOUBLES {
+                let mut mem = a;
+                let mut frame: Frame = [0; 32];
+                frame[A2] = &mut mem as *mut u64 as usize;
+                frame[A1] = b as usize;
+
+                assert!(emulate(encode(op, D, false, false, A0, A2, A1), &mut frame));
+
+                let (rd, new) = reference(op, 8, a, b);
+                assert_eq!(frame[A0] as u64, rd, "{op:?} {a:#x} {b:#x}");
+                assert_eq!(mem, new, "{op:?} {a:#x} {b:#x}");
+            }
+        }
+    }
+
+    #[test]
+    fn lr_sc_word_and_double() {
+        let mut words = [0x8000_0001u32, SENTINEL];
+        let mut frame: Frame = [0; 32];
+        frame[A2] = words.as_mut_ptr() as usize;
+        frame[A1] = 0xffff_ffff_0000_0042;
+
+        assert!(emulate(
+            encode(Op::Lr, W, false, false, A0, A2, 0),
+            &mut frame
+        ));
+        assert_eq!(frame[A0], 0xffff_ffff_8000_0001);
+        assert!(emulate(
+            encode(Op::Sc, W, false, false, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(frame[A0], 0);
+        assert_eq!(words, [0x42, SENTINEL]);
+
+        let mut double = 0x8000_0000_0000_0001u64;
+        frame[A2] = &mut double as *mut u64 as usize;
+        frame[A1] = 0x0123_4567_89ab_cdef;
+
+        assert!(emulate(
+            encode(Op::Lr, D, false, false, A0, A2, 0),
+            &mut frame
+        ));
+        assert_eq!(frame[A0], 0x8000_0000_0000_0001);
+        assert!(emulate(
+            encode(Op::Sc, D, false, false, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(frame[A0], 0);
+        assert_eq!(double, 0x0123_4567_89ab_cdef);
+
+        // the reservation is consumed by a successful SC
+        assert!(emulate(
+            encode(Op::Sc, D, false, false, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(frame[A0], 1);
+    }
+}
+
+/// Misaligned addresses are reported, never accessed.
+#[cfg(all(feature = "zalrsc", feature = "zaamo"))]
+mod misaligned {
+    use super::*;
+
+    use riscv_atomic_emulation_trap::Access;
+
+    #[test]
+    fn misaligned_addresses_are_reported() {
+        let _serial = serial();
+
+        for (funct3, bytes) in WIDTHS {
+            for offset in 1..bytes {
+                let mut mem = [0u64; 2];
+                let addr = mem.as_mut_ptr() as usize + offset;
+
+                for op in AMOS.into_iter().chain([Op::Lr, Op::Sc]) {
+                    let rs2 = if op == Op::Lr { 0 } else { A1 };
+                    let access = if op == Op::Lr {
+                        Access::Load
+                    } else {
+                        Access::Store
+                    };
+                    let mut frame = frame();
+                    frame[A2] = addr;
+                    let expected_frame = frame;
+
+                    let result = outcome(encode(op, funct3, false, false, A0, A2, rs2), &mut frame);
+                    assert_eq!(
+                        result,
+                        EmulationOutcome::MisalignedAddress { addr, access },
+                        "{op:?} +{offset}"
+                    );
+                    assert_eq!(
+                        result.exception_code(),
+                        Some(if op == Op::Lr { 4 } else { 6 })
+                    );
+                    assert_eq!(frame, expected_frame);
+                    assert_eq!(mem, [0; 2]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn misaligned_lr_takes_no_reservation() {
+        let _serial = serial();
+
+        let mut mem = [0u64; 2];
+        let mut frame = frame();
+        frame[A2] = mem.as_mut_ptr() as usize;
+        assert!(emulate(
+            encode(Op::Lr, W, false, false, A0, A2, 0),
+            &mut frame
+        ));
+
+        // a misaligned LR neither replaces the reservation nor...
+        frame[A2] += 2;
+        assert!(!emulate(
+            encode(Op::Lr, W, false, false, A0, A2, 0),
+            &mut frame
+        ));
+        // ...does a misaligned SC consume it
+        assert!(!emulate(
+            encode(Op::Sc, W, false, false, A0, A2, A1),
+            &mut frame
+        ));
+
+        frame[A2] -= 2;
+        assert!(emulate(
+            encode(Op::Sc, W, false, false, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(frame[A0], 0);
+    }
+}
+
+/// Reservation invalidation by explicit calls and policy hooks.
+#[cfg(feature = "zalrsc")]
+mod reservation {
+    use super::*;
+
+    use riscv_atomic_emulation_trap::reservation::{
+        invalidate_reservation, on_interrupt, on_trap, set_reservation_policy, ReservationPolicy,
+    };
+
+    /// Runs `LR`, then `between`, then `SC` on the same word and returns the `SC` result.
+    fn lr_sc(between: impl FnOnce(&mut Frame)) -> usize {
+        let mut mem = 0u32;
+        let mut frame = frame();
+        frame[A2] = &mut mem as *mut u32 as usize;
+        frame[A1] = 1;
+
+        assert!(emulate(
+            encode(Op::Lr, W, false, false, A0, A2, 0),
+            &mut frame
+        ));
+        between(&mut frame);
+        assert!(emulate(
+            encode(Op::Sc, W, false, false, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(mem, (frame[A0] == 0) as u32);
+        frame[A0]
+    }
+
+    #[test]
+    fn invalidate_breaks_reservation() {
+        let _serial = serial();
+        set_reservation_policy(ReservationPolicy::Manual);
+
+        assert_eq!(lr_sc(|_| {}), 0);
+        assert_eq!(lr_sc(|_| invalidate_reservation()), 1);
+    }
+
+    #[test]
+    fn failed_sc_consumes_reservation() {
+        let _serial = serial();
+        set_reservation_policy(ReservationPolicy::Manual);
+
+        let mut mem = 0u32;
+        let mut other = 0u32;
+        let mut frame = frame();
+        frame[A2] = &mut mem as *mut u32 as usize;
+        assert!(emulate(
+            encode(Op::Lr, W, false, false, A0, A2, 0),
+            &mut frame
+        ));
+
+        frame[A2] = &mut other as *mut u32 as usize;
+        assert!(emulate(
+            encode(Op::Sc, W, false, false, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(frame[A0], 1);
+
+        frame[A2] = &mut mem as *mut u32 as usize;
+        assert!(emulate(
+            encode(Op::Sc, W, false, false, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(frame[A0], 1);
+    }
+
+    #[test]
+    fn manual_policy_ignores_hooks() {
+        let _serial = serial();
+        set_reservation_policy(ReservationPolicy::Manual);
+
+        assert_eq!(
+            lr_sc(|_| {
+                on_trap();
+                on_interrupt();
+            }),
+            0
+        );
+    }
+
+    #[cfg(feature = "zaamo")]
+    #[test]
+    fn on_trap_policy() {
+        let _serial = serial();
+        set_reservation_policy(ReservationPolicy::OnTrap);
+
+        assert_eq!(lr_sc(|_| on_trap()), 1);
+        assert_eq!(lr_sc(|_| on_interrupt()), 1);
+        // an emulated AMO is a trap too
+        assert_eq!(
+            lr_sc(|frame| {
+                let mut other = 0u32;
+                let addr = frame[A2];
+                frame[A2] = &mut other as *mut u32 as usize;
+                assert!(emulate(encode(Op::Add, W, false, false, A3, A2, A1), frame));
+                frame[A2] = addr;
+            }),
+            1
+        );
+
+        set_reservation_policy(ReservationPolicy::Manual);
+    }
+
+    #[test]
+    fn on_interrupt_policy() {
+        let _serial = serial();
+        set_reservation_policy(ReservationPolicy::OnInterrupt);
+
+        assert_eq!(lr_sc(|_| on_trap()), 0);
+        assert_eq!(lr_sc(|_| on_interrupt()), 1);
+
+        set_reservation_policy(ReservationPolicy::Manual);
+    }
+}
+
+/// Only the instruction groups selected by the `zalrsc` and `zaamo` features are emulated.
+mod groups {
+    use super::*;
+
+    fn lr_sc(frame: &mut Frame, mem: &mut u32) -> [EmulationOutcome; 2] {
+        frame[A2] = mem as *mut u32 as usize;
+        [
+            outcome(encode(Op::Lr, W, false, false, A0, A2, 0), frame),
+            outcome(encode(Op::Sc, W, false, false, A0, A2, A1), frame),
+        ]
+    }
+
+    fn amos(frame: &mut Frame, mem: &mut u32) -> Vec<EmulationOutcome> {
+        frame[A2] = mem as *mut u32 as usize;
+        let mut insns: Vec<_> = AMOS
+            .into_iter()
+            .chain([Op::Cas])
+            .map(|op| encode(op, W, false, false, A0, A2, A1))
+            .collect();
+        insns.push(encode(Op::Add, B, false, false, A0, A2, A1));
+        insns.into_iter().map(|insn| outcome(insn, frame)).collect()
+    }
+
+    #[test]
+    fn lr_sc_follow_zalrsc() {
+        let _serial = serial();
+        let mut mem = 1u32;
+        let mut frame = frame();
+
+        for outcome in lr_sc(&mut frame, &mut mem) {
+            assert_eq!(
+                outcome.is_emulated(),
+                cfg!(feature = "zalrsc"),
+                "{outcome:?}"
+            );
+            if !cfg!(feature = "zalrsc") {
+                assert_eq!(outcome, EmulationOutcome::NotAtomic);
+            }
+        }
+        if !cfg!(feature = "zalrsc") {
+            assert_eq!((mem, frame[A0]), (1, 0x1000 + A0));
+        }
+    }
+
+    #[test]
+    fn amos_follow_zaamo() {
+        let _serial = serial();
+        let mut mem = 1u32;
+        let mut frame = frame();
+
+        for outcome in amos(&mut frame, &mut mem) {
+            assert_eq!(
+                outcome.is_emulated(),
+                cfg!(feature = "zaamo"),
+                "{outcome:?}"
+            );
+            if !cfg!(feature = "zaamo") {
+                assert_eq!(outcome, EmulationOutcome::NotAtomic);
+            }
+        }
+        if !cfg!(feature = "zaamo") {
+            assert_eq!((mem, frame[A0]), (1, 0x1000 + A0));
+        }
+    }
+}
+
+/// Zacas compare-and-swap emulation.
+mod zacas {
+    use super::*;
+
+    use riscv_atomic_emulation_trap::decode::{decode, AtomicInsn, DecodeError, Width};
+
+    #[test]
+    fn decodes_amocas() {
+        for (funct3, width) in [(W, Width::Word), (D, Width::Double), (Q, Width::Quad)] {
+            let insn = decode(encode(Op::Cas, funct3, true, true, A0, A2, A1)).unwrap();
+            assert!(matches!(insn, AtomicInsn::AmoCas(_)));
+            assert_eq!(insn.operands().width, width);
+        }
+    }
+
+    #[test]
+    fn quad_width_is_only_valid_for_amocas() {
+        for op in AMOS.into_iter().chain([Op::Lr, Op::Sc]) {
+            assert_eq!(
+                decode(encode(op, Q, false, false, A0, A2, 0)),
+                Err(DecodeError::InvalidWidth),
+                "{op:?}"
+            );
+        }
+    }
+
+    #[cfg(feature = "zaamo")]
+    #[test]
+    fn word_swaps_on_match() {
+        let mut mem = [0x8000_0000u32, 0x5a5a_5a5a];
+        let mut frame = frame();
+        frame[A2] = mem.as_mut_ptr() as usize;
+        // only the low 32 bits of rd take part in the comparison
+        frame[A0] = 0xdead_beef_8000_0000u64 as usize;
+        frame[A1] = 7;
+
+        assert!(emulate(
+            encode(Op::Cas, W, false, false, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(mem, [7, 0x5a5a_5a5a]);
+        // the old value is sign-extended into rd
+        assert_eq!(frame[A0], 0x8000_0000u32 as i32 as usize);
+    }
+
+    #[cfg(feature = "zaamo")]
+    #[test]
+    fn word_keeps_memory_on_mismatch() {
+        let mut mem = 5u32;
+        let mut frame = frame();
+        frame[A2] = &mut mem as *mut u32 as usize;
+        frame[A0] = 6;
+        frame[A1] = 7;
+
+        assert!(emulate(
+            encode(Op::Cas, W, false, false, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(mem, 5);
+        assert_eq!(frame[A0], 5);
+    }
+
+    #[cfg(feature = "zaamo")]
+    #[test]
+    fn x0_compares_zero() {
+        let mut mem = 0u32;
+        let mut frame = frame();
+        frame[A2] = &mut mem as *mut u32 as usize;
+        frame[A1] = 9;
+
+        assert!(emulate(
+            encode(Op::Cas, W, false, false, 0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(mem, 9);
+        assert_eq!(frame[0], 0);
+    }
+
+    #[cfg(feature = "zaamo")]
+    #[test]
+    fn misaligned_is_reported_as_store() {
+        let mut mem = [0u32; 2];
+        let mut frame = frame();
+        let addr = mem.as_mut_ptr() as usize + 2;
+        frame[A2] = addr;
+
+        assert_eq!(
+            outcome(encode(Op::Cas, W, false, false, A0, A2, A1), &mut frame),
+            EmulationOutcome::MisalignedAddress {
+                addr,
+                access: riscv_atomic_emulation_trap::Access::Store
+            }
+        );
+    }
+
+    #[cfg(all(target_pointer_width = "64", feature = "zaamo"))]
+    mod rv64 {
+        use super::*;
+
+        #[test]
+        fn double_uses_single_registers() {
+            let mut mem = 0x1234_5678_9abc_def0u64;
+            let mut frame = frame();
+            frame[A2] = &mut mem as *mut u64 as usize;
+            frame[A0] = mem as usize;
+            frame[A1] = 0x0fed_cba9_8765_4321;
+
+            assert!(emulate(
+                encode(Op::Cas, D, false, false, A0, A2, A1),
+                &mut frame
+            ));
+            assert_eq!(mem, 0x0fed_cba9_8765_4321);
+            assert_eq!(frame[A0], 0x1234_5678_9abc_def0);
+            // odd registers are fine outside of pairs
+            assert!(emulate(
+                encode(Op::Cas, D, false, false, A1, A2, A3),
+                &mut frame
+            ));
+        }
+
+        #[repr(align(16))]
+        struct Quad(u128);
+
+        #[test]
+        fn quad_uses_register_pairs() {
+            let mut mem = Quad(0x1111_2222_3333_4444_5555_6666_7777_8888);
+            let mut frame = frame();
+            frame[A2] = &mut mem.0 as *mut u128 as usize;
+            (frame[A0], frame[A0 + 1]) = (0x5555_6666_7777_8888, 0x1111_2222_3333_4444);
+            (frame[A4], frame[A4 + 1]) = (0xdddd_eeee_ffff_0000, 0x9999_aaaa_bbbb_cccc);
+
+            assert!(emulate(
+                encode(Op::Cas, Q, false, false, A0, A2, A4),
+                &mut frame
+            ));
+            assert_eq!(mem.0, 0x9999_aaaa_bbbb_cccc_dddd_eeee_ffff_0000);
+            assert_eq!(frame[A0], 0x5555_6666_7777_8888);
+            assert_eq!(frame[A0 + 1], 0x1111_2222_3333_4444);
+
+            // the expected value no longer matches
+            assert!(emulate(
+                encode(Op::Cas, Q, false, false, A0, A2, A4),
+                &mut frame
+            ));
+            assert_eq!(mem.0, 0x9999_aaaa_bbbb_cccc_dddd_eeee_ffff_0000);
+            assert_eq!(frame[A0], 0xdddd_eeee_ffff_0000);
+            assert_eq!(frame[A0 + 1], 0x9999_aaaa_bbbb_cccc);
+        }
+
+        #[test]
+        fn quad_x0_pair_reads_zero_and_discards_writes() {
+            let mut mem = Quad(0);
+            let mut frame = frame();
+            frame[A2] = &mut mem.0 as *mut u128 as usize;
+
+            // x1 is not part of the comparison, the x0 pair is zero
+            assert!(emulate(
+                encode(Op::Cas, Q, false, false, 0, A2, A4),
+                &mut frame
+            ));
+            assert_eq!(mem.0, (frame[A4 + 1] as u128) << 64 | frame[A4] as u128);
+            assert_eq!((frame[0], frame[1]), (0, 0x1001));
+
+            // storing the x0 pair clears memory
+            (frame[A0], frame[A0 + 1]) = (frame[A4], frame[A4 + 1]);
+            assert!(emulate(
+                encode(Op::Cas, Q, false, false, A0, A2, 0),
+                &mut frame
+            ));
+            assert_eq!(mem.0, 0);
+        }
+
+        #[test]
+        fn quad_rejects_odd_registers() {
+            let mut mem = Quad(0);
+            let mut frame = frame();
+            frame[A2] = &mut mem.0 as *mut u128 as usize;
+            let before = frame;
+
+            for (rd, rs2) in [(A1, A4), (A0, A3), (1, 0)] {
+                assert_eq!(
+                    outcome(encode(Op::Cas, Q, false, false, rd, A2, rs2), &mut frame),
+                    EmulationOutcome::IllegalEncoding
+                );
+            }
+            assert_eq!(frame, before);
+            assert_eq!(mem.0, 0);
+        }
+
+        #[test]
+        fn quad_must_be_16_byte_aligned() {
+            let mut mem = [Quad(0), Quad(0)];
+            let mut frame = frame();
+            let addr = &mut mem[0].0 as *mut u128 as usize + 8;
+            frame[A2] = addr;
+
+            assert!(matches!(
+                outcome(encode(Op::Cas, Q, false, false, A0, A2, A4), &mut frame),
+                EmulationOutcome::MisalignedAddress { addr: a, .. } if a == addr
+            ));
+        }
+    }
+
+    #[cfg(all(not(target_pointer_width = "64"), feature = "zaamo"))]
+    mod rv32 {
+        use super::*;
+
+        #[test]
+        fn double_uses_register_pairs() {
+            let mut mem = 0x1234_5678_9abc_def0u64;
+            let mut frame = frame();
+            frame[A2] = &mut mem as *mut u64 as usize;
+            (frame[A0], frame[A0 + 1]) = (0x9abc_def0, 0x1234_5678);
+            (frame[A4], frame[A4 + 1]) = (0x8765_4321, 0x0fed_cba9);
+
+            assert!(emulate(
+                encode(Op::Cas, D, false, false, A0, A2, A4),
+                &mut frame
+            ));
+            assert_eq!(mem, 0x0fed_cba9_8765_4321);
+            assert_eq!((frame[A0], frame[A0 + 1]), (0x9abc_def0, 0x1234_5678));
+
+            assert_eq!(
+                outcome(encode(Op::Cas, D, false, false, A1, A2, A4), &mut frame),
+                EmulationOutcome::IllegalEncoding
+            );
+        }
+
+        #[test]
+        fn quad_is_reserved() {
+            let mut frame = frame();
+            assert_eq!(
+                outcome(encode(Op::Cas, Q, false, false, A0, A2, A4), &mut frame),
+                EmulationOutcome::IllegalEncoding
+            );
+        }
+    }
+}
+
+/// Zabha byte and halfword emulation, checked against the reference model.
+#[cfg(feature = "zaamo")]
+mod zabha {
+    use super::*;
+
+    use riscv_atomic_emulation_trap::decode::{decode, DecodeError};
+    use riscv_atomic_emulation_trap::Access;
+
+    const BYTES: [(u8, u8); 5] = [(5, 7), (0x80, 3), (0x7f, 1), (0xf0, 0x0e), (0, 0x81)];
+
+    const HALVES: [(u16, u16); 5] = [
+        (5, 7),
+        (0x8000, 3),
+        (0x7fff, 1),
+        (0xfff0, 0x000e),
+        (0x1234, 0x8001),
+    ];
+
+    #[test]
+    fn amo_byte_matches_reference() {
+        for op in AMOS {
+            for (a, b) in BYTES {
+                let mut mem = [0xa5, a, 0x5a, 0xa5];
+                let mut frame = frame();
+                frame[A2] = &mut mem[1] as *mut u8 as usize;
+                // the upper bits of rs2 must be ignored
+                frame[A1] = 0xbeef_ff00 | b as usize;
+
+                assert!(emulate(encode(op, B, false, false, A0, A2, A1), &mut frame));
+
+                let (rd, new) = reference(op, 1, a as u64, b as u64);
+                assert_eq!(frame[A0], rd as usize, "{op:?} {a:#x} {b:#x}");
+                assert_eq!(mem, [0xa5, new as u8, 0x5a, 0xa5], "{op:?} {a:#x} {b:#x}");
+            }
+        }
+    }
+
+    #[test]
+    fn amo_half_matches_reference() {
+        for op in AMOS {
+            for (a, b) in HALVES {
+                let mut mem = [0xa5a5, a, 0x5a5a];
+                let mut frame = frame();
+                frame[A2] = &mut mem[1] as *mut u16 as usize;
+                frame[A1] = 0xbeef_0000 | b as usize;
+
+                assert!(emulate(encode(op, H, false, false, A0, A2, A1), &mut frame));
+
+                let (rd, new) = reference(op, 2, a as u64, b as u64);
+                assert_eq!(frame[A0], rd as usize, "{op:?} {a:#x} {b:#x}");
+                assert_eq!(mem, [0xa5a5, new as u16, 0x5a5a], "{op:?} {a:#x} {b:#x}");
+            }
+        }
+    }
+
+    #[test]
+    fn amocas_byte_and_half() {
+        let mut byte = 0x80u8;
+        let mut frame = frame();
+        frame[A2] = &mut byte as *mut u8 as usize;
+        frame[A0] = 0xff80;
+        frame[A1] = 1;
+        assert!(emulate(
+            encode(Op::Cas, B, false, false, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(byte, 1);
+        assert_eq!(frame[A0], 0x80u8 as i8 as usize);
+
+        let mut half = 0x1234u16;
+        frame[A2] = &mut half as *mut u16 as usize;
+        frame[A0] = 0x1235;
+        assert!(emulate(
+            encode(Op::Cas, H, false, false, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(half, 0x1234);
+        assert_eq!(frame[A0], 0x1234);
+    }
+
+    #[test]
+    fn no_byte_or_half_lr_sc() {
+        for funct3 in [B, H] {
+            for op in [Op::Lr, Op::Sc] {
+                let insn = encode(op, funct3, false, false, A0, A2, 0);
+                assert_eq!(decode(insn), Err(DecodeError::InvalidWidth));
+                assert_eq!(
+                    outcome(insn, &mut frame()),
+                    EmulationOutcome::IllegalEncoding
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn misaligned_half() {
+        let mut mem = [0u16; 2];
+        let mut frame = frame();
+        let addr = mem.as_mut_ptr() as usize + 1;
+        frame[A2] = addr;
+
+        assert_eq!(
+            outcome(encode(Op::Add, H, false, false, A0, A2, A1), &mut frame),
+            EmulationOutcome::MisalignedAddress {
+                addr,
+                access: Access::Store
+            }
+        );
+        assert_eq!(mem, [0, 0]);
+    }
+}
@@ -0,0 +1,221 @@
+//! Named trap frames, conversions to the `riscv-rt` and `esp-hal` layouts and emulation on other
+//! register files.
+
+mod common;
+
+use core::mem::size_of;
+
+use common::*;
+use riscv_atomic_emulation_trap::frame::{EspHalTrapFrame, RiscvRtTrapFrame};
+use riscv_atomic_emulation_trap::TrapFrame;
+
+#[test]
+fn accessors_follow_abi_names() {
+    let frame = TrapFrame::new(frame());
+    let regs = frame.registers();
+
+    assert_eq!(regs[0], 0);
+    assert_eq!(frame.ra(), regs[1]);
+    assert_eq!(frame.sp(), regs[2]);
+    assert_eq!(frame.s0(), regs[8]);
+    assert_eq!(frame.a0(), regs[A0]);
+    assert_eq!(frame.a7(), regs[17]);
+    assert_eq!(frame.s11(), regs[27]);
+    assert_eq!(frame.t6(), regs[31]);
+}
+
+#[test]
+fn x0_is_stored_as_zero() {
+    assert_eq!(TrapFrame::from([1; 32]).registers()[0], 0);
+}
+
+#[test]
+fn layouts() {
+    assert_eq!(size_of::<TrapFrame>(), 32 * size_of::<usize>());
+    assert_eq!(size_of::<RiscvRtTrapFrame>(), 16 * size_of::<usize>());
+    assert_eq!(size_of::<EspHalTrapFrame>(), 35 * size_of::<usize>());
+}
+
+#[test]
+fn converts_to_riscv_rt() {
+    let mut frame = TrapFrame::default();
+    frame.set_ra(1);
+    frame.set_sp(2);
+    frame.set_t0(5);
+    frame.set_s0(8);
+    frame.set_a0(10);
+    frame.set_a7(17);
+    frame.set_t3(28);
+
+    // the callee saved registers are not part of riscv-rt's frame
+    let rt = RiscvRtTrapFrame {
+        ra: 1,
+        t0: 5,
+        t3: 28,
+        a0: 10,
+        a7: 17,
+        ..Default::default()
+    };
+    assert_eq!(RiscvRtTrapFrame::from(&frame), rt);
+}
+
+#[test]
+fn esp_hal_round_trip() {
+    let mut esp = EspHalTrapFrame {
+        ra: 1,
+        sp: 2,
+        gp: 3,
+        s0: 8,
+        a2: 12,
+        s11: 27,
+        t6: 31,
+        pc: 0x4200_0000,
+        mcause: 2,
+        ..Default::default()
+    };
+    let mut frame = TrapFrame::from(&esp);
+    assert_eq!(frame.registers()[..4], [0, 1, 2, 3]);
+    assert_eq!(frame.registers()[8], 8);
+    assert_eq!(frame.registers()[A2], 12);
+    assert_eq!(frame.registers()[27], 27);
+    assert_eq!(frame.registers()[31], 31);
+
+    frame.set_a0(42);
+    frame.write_to_esp_hal(&mut esp);
+    assert_eq!(esp.a0, 42);
+    assert_eq!((esp.sp, esp.pc, esp.mcause), (2, 0x4200_0000, 2));
+}
+
+#[cfg(feature = "zaamo")]
+#[test]
+fn emulates_on_trap_frame() {
+    let mut frame = TrapFrame::default();
+    assert_amoadd_40_plus_2(&mut frame);
+    assert_eq!(frame.a0(), 40);
+}
+
+/// Emulation on register files in layouts other than an `x0-x31` array.
+#[cfg(feature = "zaamo")]
+mod registers {
+    use super::*;
+
+    use riscv_atomic_emulation_trap::frame::EspHalTrapFrame;
+    use riscv_atomic_emulation_trap::{EmulationOutcome, RegisterFile};
+
+    /// Registers saved in reverse order without `x0`, as some RTOS context switchers do.
+    struct Reversed([usize; 31]);
+
+    impl RegisterFile for Reversed {
+        const LEN: usize = 32;
+
+        fn read(&self, xreg: usize) -> usize {
+            match xreg {
+                0 => 0,
+                x => self.0[31 - x],
+            }
+        }
+
+        fn write(&mut self, xreg: usize, value: usize) {
+            if xreg != 0 {
+                self.0[31 - xreg] = value;
+            }
+        }
+    }
+
+    #[test]
+    fn emulates_on_custom_layout() {
+        let mut regs = Reversed([0; 31]);
+        assert_amoadd_40_plus_2(&mut regs);
+        assert_eq!(regs.0[31 - A0], 40);
+    }
+
+    #[test]
+    fn emulates_on_esp_hal_frame_in_place() {
+        let mut mem = 5u32;
+        let mut frame = EspHalTrapFrame {
+            a2: &mut mem as *mut u32 as usize,
+            a1: 7,
+            pc: 0x4200_0000,
+            ..Default::default()
+        };
+
+        assert!(emulate(
+            encode(Op::Swap, W, false, false, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!((frame.a0, mem), (5, 7));
+        assert_eq!(frame.pc, 0x4200_0000);
+    }
+
+    #[test]
+    fn x0_reads_zero_whatever_is_stored() {
+        let mut mem = 5u32;
+        let mut frame = frame();
+        // a trap entry may leave anything in the x0 slot
+        frame[0] = 0xdead;
+        frame[A2] = &mut mem as *mut u32 as usize;
+
+        assert!(emulate(
+            encode(Op::Swap, W, false, false, 0, A2, 0),
+            &mut frame
+        ));
+        assert_eq!(mem, 0);
+        assert_eq!(frame[0], 0xdead);
+    }
+
+    /// Only `x0-x15` are saved.
+    struct Reduced([usize; 16]);
+
+    impl RegisterFile for Reduced {
+        const LEN: usize = 16;
+
+        fn read(&self, xreg: usize) -> usize {
+            self.0.read(xreg)
+        }
+
+        fn write(&mut self, xreg: usize, value: usize) {
+            self.0.write(xreg, value)
+        }
+    }
+
+    #[test]
+    fn registers_beyond_len_are_illegal() {
+        let mut regs = Reduced([0; 16]);
+        assert_eq!(
+            outcome(encode(Op::Add, W, false, false, A0, A2, 16), &mut regs),
+            EmulationOutcome::IllegalEncoding
+        );
+    }
+}
+
+/// Emulation with the 16 register frame of RV32E/RV64E cores.
+#[cfg(all(feature = "zalrsc", feature = "zaamo"))]
+mod reduced {
+    use super::*;
+
+    use riscv_atomic_emulation_trap::{EmulationOutcome, E_REGISTER_LEN};
+
+    #[test]
+    fn emulates_within_reduced_register_file() {
+        assert_amoadd_40_plus_2(&mut [0usize; E_REGISTER_LEN]);
+    }
+
+    #[test]
+    fn rejects_registers_outside_reduced_register_file() {
+        let mut mem = 40u32;
+        let mut frame = [0usize; E_REGISTER_LEN];
+        frame[A2] = &mut mem as *mut u32 as usize;
+
+        for (rd, rs1, rs2) in [(16, A2, A1), (A0, 17, A1), (A0, A2, 31)] {
+            assert_eq!(
+                outcome(encode(Op::Add, W, false, false, rd, rs1, rs2), &mut frame),
+                EmulationOutcome::IllegalEncoding
+            );
+        }
+        assert_eq!(
+            outcome(encode(Op::Lr, W, false, false, 20, A2, 0), &mut frame),
+            EmulationOutcome::IllegalEncoding
+        );
+        assert_eq!(mem, 40);
+    }
+}
@@ -0,0 +1,199 @@
+//! Reservations are tracked per hart, each test thread pretending to be one.
+
+mod common;
+
+use common::*;
+use riscv_atomic_emulation_trap::hart::{HARTS, UNKNOWN_HART};
+use riscv_atomic_emulation_trap::reservation::invalidate_hart_reservation;
+use riscv_atomic_emulation_trap::EmulationOutcome;
+
+const _: () = assert!(
+    HARTS >= 2,
+    "the host tests are built for a multi hart system"
+);
+
+fn lr(frame: &mut Frame, addr: &mut u32) {
+    frame[A2] = addr as *mut u32 as usize;
+    assert!(emulate(encode(Op::Lr, W, false, false, A0, A2, 0), frame));
+}
+
+fn sc(frame: &mut Frame, addr: &mut u32) -> usize {
+    frame[A2] = addr as *mut u32 as usize;
+    assert!(emulate(encode(Op::Sc, W, false, false, A0, A2, A1), frame));
+    frame[A0]
+}
+
+#[test]
+fn sc_on_other_hart_fails() {
+    let _serial = serial();
+    let mut mem = 0u32;
+    let mut frame = frame();
+
+    on_hart(0);
+    lr(&mut frame, &mut mem);
+    on_hart(1);
+    assert_eq!(sc(&mut frame, &mut mem), 1);
+    on_hart(0);
+    assert_eq!(sc(&mut frame, &mut mem), 0);
+}
+
+#[test]
+fn harts_hold_independent_reservations() {
+    let _serial = serial();
+    let (mut x, mut y) = (0u32, 0u32);
+    let mut frame = frame();
+
+    on_hart(1);
+    lr(&mut frame, &mut x);
+    on_hart(0);
+    lr(&mut frame, &mut y);
+    assert_eq!(sc(&mut frame, &mut y), 0);
+    on_hart(1);
+    assert_eq!(sc(&mut frame, &mut x), 0);
+}
+
+#[test]
+fn stores_from_other_harts_break_reservation() {
+    let _serial = serial();
+    let mut mem = 0u32;
+    let mut frame = frame();
+
+    // by an AMO
+    on_hart(0);
+    lr(&mut frame, &mut mem);
+    on_hart(1);
+    frame[A2] = &mut mem as *mut u32 as usize;
+    assert!(emulate(
+        encode(Op::Add, W, false, false, A3, A2, A1),
+        &mut frame
+    ));
+    on_hart(0);
+    assert_eq!(sc(&mut frame, &mut mem), 1);
+
+    // by a successful SC
+    lr(&mut frame, &mut mem);
+    on_hart(1);
+    lr(&mut frame, &mut mem);
+    assert_eq!(sc(&mut frame, &mut mem), 0);
+    on_hart(0);
+    assert_eq!(sc(&mut frame, &mut mem), 1);
+}
+
+#[test]
+fn narrower_stores_inside_reservation_break_it() {
+    let _serial = serial();
+    let mut mem = [0u8; 4];
+    let mut frame = frame();
+
+    on_hart(0);
+    frame[A2] = mem.as_mut_ptr() as usize;
+    assert!(emulate(
+        encode(Op::Lr, W, false, false, A0, A2, 0),
+        &mut frame
+    ));
+    on_hart(1);
+    frame[A2] += 1;
+    frame[A1] = 5;
+    assert!(emulate(
+        encode(Op::Add, B, false, false, A3, A2, A1),
+        &mut frame
+    ));
+    assert_eq!(mem, [0, 5, 0, 0]);
+
+    on_hart(0);
+    frame[A2] -= 1;
+    frame[A1] = 17;
+    assert!(emulate(
+        encode(Op::Sc, W, false, false, A0, A2, A1),
+        &mut frame
+    ));
+    assert_eq!(frame[A0], 1);
+    assert_eq!(mem, [0, 5, 0, 0]);
+}
+
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn stores_overlapping_a_doubleword_reservation_break_it() {
+    let _serial = serial();
+    #[repr(align(16))]
+    struct Quad([u64; 2]);
+    let mut mem = Quad([0; 2]);
+    let base = mem.0.as_mut_ptr() as usize;
+    let mut frame = frame();
+
+    // a word store to the upper half of the reserved doubleword
+    on_hart(0);
+    frame[A2] = base;
+    assert!(emulate(
+        encode(Op::Lr, D, false, false, A0, A2, 0),
+        &mut frame
+    ));
+    on_hart(1);
+    frame[A2] = base + 4;
+    assert!(emulate(
+        encode(Op::Swap, W, false, false, A3, A2, A1),
+        &mut frame
+    ));
+    on_hart(0);
+    frame[A2] = base;
+    assert!(emulate(
+        encode(Op::Sc, D, false, false, A0, A2, A1),
+        &mut frame
+    ));
+    assert_eq!(frame[A0], 1);
+
+    // the upper half of an AMOCAS.Q
+    frame[A2] = base + 8;
+    assert!(emulate(
+        encode(Op::Lr, D, false, false, A0, A2, 0),
+        &mut frame
+    ));
+    on_hart(1);
+    frame[A2] = base;
+    frame[A0] = mem.0[0] as usize;
+    frame[A0 + 1] = mem.0[1] as usize;
+    assert!(emulate(
+        encode(Op::Cas, Q, false, false, A0, A2, A4),
+        &mut frame
+    ));
+    on_hart(0);
+    frame[A2] = base + 8;
+    assert!(emulate(
+        encode(Op::Sc, D, false, false, A0, A2, A1),
+        &mut frame
+    ));
+    assert_eq!(frame[A0], 1);
+}
+
+#[test]
+fn invalidate_other_hart() {
+    let _serial = serial();
+    let mut mem = 0u32;
+    let mut frame = frame();
+
+    on_hart(1);
+    lr(&mut frame, &mut mem);
+    invalidate_hart_reservation(1);
+    assert_eq!(sc(&mut frame, &mut mem), 1);
+}
+
+#[test]
+fn harts_out_of_range_are_not_emulated() {
+    let _serial = serial();
+    let mut mem = 0u32;
+    let mut frame = frame();
+    frame[A2] = &mut mem as *mut u32 as usize;
+    let expected = frame;
+
+    for hart in [HARTS, UNKNOWN_HART] {
+        on_hart(hart);
+        for insn in [
+            encode(Op::Lr, W, false, false, A0, A2, 0),
+            encode(Op::Sc, W, false, false, A0, A2, A1),
+        ] {
+            assert_eq!(outcome(insn, &mut frame), EmulationOutcome::NotAtomic);
+        }
+    }
+    assert_eq!((frame, mem), (expected, 0));
+    on_hart(0);
+}
@@ -0,0 +1,119 @@
+//! Emulated AMOs from concurrent harts, each thread pretending to be one, serialized by the
+//! registered lock.
+
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use common::*;
+use riscv_atomic_emulation_trap::hart::HARTS;
+use riscv_atomic_emulation_trap::lock::{set_emulation_lock, BakeryLock, EmulationLock};
+use riscv_atomic_emulation_trap::EmulationOutcome;
+
+const ITERATIONS: usize = 200;
+
+static BAKERY: BakeryLock<HARTS> = BakeryLock::new();
+
+#[test]
+fn bakery_lock_serializes_amos() {
+    let _serial = serial();
+    unsafe { set_emulation_lock(&BAKERY) };
+
+    let mut counter = 0u32;
+    let addr = &mut counter as *mut u32 as usize;
+
+    thread::scope(|s| {
+        for hart in 0..HARTS {
+            s.spawn(move || {
+                on_hart(hart);
+                let mut frame = frame();
+                frame[A2] = addr;
+                frame[A1] = 1;
+                for _ in 0..ITERATIONS {
+                    assert!(emulate(
+                        encode(Op::Add, W, false, false, A0, A2, A1),
+                        &mut frame
+                    ));
+                }
+            });
+        }
+    });
+
+    assert_eq!(counter as usize, HARTS * ITERATIONS);
+}
+
+/// Counts how often it is taken, and checks it is never taken twice.
+struct CountingLock {
+    held: AtomicUsize,
+    taken: AtomicUsize,
+}
+
+impl EmulationLock for CountingLock {
+    fn acquire(&self) -> bool {
+        assert_eq!(self.held.swap(1, Ordering::SeqCst), 0);
+        self.taken.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    fn release(&self) {
+        assert_eq!(self.held.swap(0, Ordering::SeqCst), 1);
+    }
+}
+
+static COUNTING: CountingLock = CountingLock {
+    held: AtomicUsize::new(0),
+    taken: AtomicUsize::new(0),
+};
+
+#[test]
+fn lock_held_around_every_emulated_instruction() {
+    let _serial = serial();
+    unsafe { set_emulation_lock(&COUNTING) };
+    on_hart(0);
+
+    let mut mem = 0u32;
+    let mut frame = frame();
+    frame[A2] = &mut mem as *mut u32 as usize;
+
+    let insns = [
+        encode(Op::Lr, W, false, false, A0, A2, 0),
+        encode(Op::Sc, W, false, false, A0, A2, A1),
+        encode(Op::Add, W, false, false, A0, A2, A1),
+    ];
+    for (taken, insn) in insns.into_iter().enumerate() {
+        assert!(emulate(insn, &mut frame));
+        assert_eq!(COUNTING.taken.load(Ordering::SeqCst), taken + 1);
+    }
+
+    // addi a0, a0, 1
+    assert!(!emulate(0x0015_0513, &mut frame));
+    assert_eq!(COUNTING.taken.load(Ordering::SeqCst), insns.len());
+}
+
+#[test]
+fn bakery_lock_refuses_harts_out_of_range() {
+    static BAKERY: BakeryLock<HARTS> = BakeryLock::new();
+    let _serial = serial();
+    unsafe { set_emulation_lock(&BAKERY) };
+
+    let mut mem = 0u32;
+    let mut frame = frame();
+    frame[A2] = &mut mem as *mut u32 as usize;
+    let expected = frame;
+
+    on_hart(HARTS);
+    assert_eq!(
+        outcome(encode(Op::Add, W, false, false, A0, A2, A1), &mut frame),
+        EmulationOutcome::NotAtomic
+    );
+    assert_eq!((mem, frame), (0, expected));
+
+    // harts in range are unaffected
+    on_hart(0);
+    assert!(emulate(
+        encode(Op::Add, W, false, false, A0, A2, A1),
+        &mut frame
+    ));
+    assert_eq!(mem, 0x1000 + A1 as u32);
+}
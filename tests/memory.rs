@@ -0,0 +1,174 @@
+//! Emulation against a simulated address space.
+
+mod common;
+
+use common::*;
+use riscv_atomic_emulation_trap::memory::{BusError, MemoryBus, SparseMemory};
+use riscv_atomic_emulation_trap::{atomic_emulation_with_bus, Access, EmulationOutcome};
+
+const PC: usize = 0x4200_0000;
+const BASE: usize = 0x8000_0000;
+
+fn run<M: MemoryBus>(insn: u32, frame: &mut Frame, bus: &mut M) -> EmulationOutcome {
+    atomic_emulation_with_bus(PC, insn, frame, bus)
+}
+
+#[test]
+fn amo_on_sparse_memory() {
+    let _serial = serial();
+    let mut mem = SparseMemory::new();
+    mem.map(BASE, 16);
+    mem.store32(BASE + 4, 40).unwrap();
+
+    let mut frame = frame();
+    frame[A2] = BASE + 4;
+    frame[A1] = 2;
+
+    assert_eq!(
+        run(
+            encode(Op::Add, W, false, false, A0, A2, A1),
+            &mut frame,
+            &mut mem
+        ),
+        EmulationOutcome::Emulated { next_pc: PC + 4 }
+    );
+    assert_eq!(frame[A0], 40);
+    assert_eq!(mem.load32(BASE + 4), Ok(42));
+    assert_eq!(mem.load32(BASE), Ok(0));
+}
+
+#[test]
+fn lr_sc_on_sparse_memory() {
+    let _serial = serial();
+    let mut mem = SparseMemory::new();
+    mem.map(BASE, 8);
+    mem.store64(BASE, 7).unwrap();
+
+    let mut frame = frame();
+    frame[A2] = BASE;
+    frame[A1] = 9;
+
+    let lr = encode(Op::Lr, W, false, false, A0, A2, 0);
+    let sc = encode(Op::Sc, W, false, false, A3, A2, A1);
+    assert!(run(lr, &mut frame, &mut mem).is_emulated());
+    assert!(run(sc, &mut frame, &mut mem).is_emulated());
+    assert_eq!((frame[A0], frame[A3]), (7, 0));
+    assert_eq!(mem.load32(BASE), Ok(9));
+}
+
+#[test]
+fn unmapped_addresses_fault() {
+    let _serial = serial();
+    let mut mem = SparseMemory::new();
+    let mut frame = frame();
+    frame[A2] = BASE;
+    let expected = frame;
+
+    assert_eq!(
+        run(
+            encode(Op::Lr, W, false, false, A0, A2, 0),
+            &mut frame,
+            &mut mem
+        ),
+        EmulationOutcome::AccessFault {
+            addr: BASE,
+            access: Access::Load
+        }
+    );
+    for op in AMOS {
+        assert_eq!(
+            run(
+                encode(op, W, false, false, A0, A2, A1),
+                &mut frame,
+                &mut mem
+            ),
+            EmulationOutcome::AccessFault {
+                addr: BASE,
+                access: Access::Store
+            }
+        );
+    }
+    assert_eq!(frame, expected);
+}
+
+/// Memory that can be read but not written.
+struct ReadOnly(SparseMemory);
+
+impl MemoryBus for ReadOnly {
+    fn load8(&mut self, addr: usize) -> Result<u8, BusError> {
+        self.0.load8(addr)
+    }
+    fn load16(&mut self, addr: usize) -> Result<u16, BusError> {
+        self.0.load16(addr)
+    }
+    fn load32(&mut self, addr: usize) -> Result<u32, BusError> {
+        self.0.load32(addr)
+    }
+    fn load64(&mut self, addr: usize) -> Result<u64, BusError> {
+        self.0.load64(addr)
+    }
+    fn store8(&mut self, _: usize, _: u8) -> Result<(), BusError> {
+        Err(BusError)
+    }
+    fn store16(&mut self, _: usize, _: u16) -> Result<(), BusError> {
+        Err(BusError)
+    }
+    fn store32(&mut self, _: usize, _: u32) -> Result<(), BusError> {
+        Err(BusError)
+    }
+    fn store64(&mut self, _: usize, _: u64) -> Result<(), BusError> {
+        Err(BusError)
+    }
+}
+
+#[test]
+fn failed_store_leaves_rd_untouched() {
+    let _serial = serial();
+    let mut mem = SparseMemory::new();
+    mem.map(BASE, 8);
+    mem.store32(BASE, 5).unwrap();
+    let mut mem = ReadOnly(mem);
+
+    let mut frame = frame();
+    frame[A2] = BASE;
+    let expected = frame;
+
+    assert_eq!(
+        run(
+            encode(Op::Swap, W, false, false, A0, A2, A1),
+            &mut frame,
+            &mut mem
+        ),
+        EmulationOutcome::AccessFault {
+            addr: BASE,
+            access: Access::Store
+        }
+    );
+    assert_eq!(frame, expected);
+
+    // a failing AMOCAS compare never stores
+    assert!(run(
+        encode(Op::Cas, W, false, false, A0, A2, A1),
+        &mut frame,
+        &mut mem
+    )
+    .is_emulated());
+    assert_eq!(frame[A0], 5);
+}
+
+#[test]
+fn sparse_memory_maps_whole_pages() {
+    let mut mem = SparseMemory::new();
+    mem.map(BASE + 4095, 2);
+
+    assert!(mem.is_mapped(BASE));
+    assert!(mem.is_mapped(BASE + 8191));
+    assert!(!mem.is_mapped(BASE + 8192));
+    assert!(!mem.is_mapped(BASE - 1));
+
+    // little endian, as on RISC-V
+    mem.write(BASE + 4094, &[1, 2, 3, 4]).unwrap();
+    assert_eq!(mem.load32(BASE + 4094), Ok(0x0403_0201));
+    assert_eq!(mem.write(BASE + 8190, &[0; 4]), Err(BusError));
+    assert_eq!(mem.load16(BASE + 8190), Ok(0));
+}
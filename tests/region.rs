@@ -0,0 +1,145 @@
+//! Accesses outside of the allowed regions fault instead of reaching memory, MMIO regions are
+//! accessed with volatile reads and writes.
+#![cfg(feature = "zaamo")]
+
+mod common;
+
+use core::ptr::addr_of_mut;
+
+use common::*;
+use riscv_atomic_emulation_trap::region::{is_allowed, set_allowed_regions, Region};
+use riscv_atomic_emulation_trap::{Access, EmulationOutcome};
+
+static mut RAM: [u64; 2] = [0; 2];
+static mut OUTSIDE: [u32; 1] = [7];
+
+/// Only the first 12 bytes of `RAM` are allowed.
+static ALLOWED: [Region; 1] = [Region::new(
+    addr_of_mut!(RAM) as *const u8,
+    (addr_of_mut!(RAM) as *const u8).wrapping_add(12),
+)];
+
+fn setup() -> (usize, usize) {
+    unsafe { set_allowed_regions(&ALLOWED) };
+    (addr_of_mut!(RAM) as usize, addr_of_mut!(OUTSIDE) as usize)
+}
+
+#[test]
+fn region_bounds() {
+    let region = Region::new(0x1000 as *const u8, 0x2000 as *const u8);
+    assert!(region.contains(0x1000, 4));
+    assert!(region.contains(0x1ffc, 4));
+    assert!(!region.contains(0x1ffe, 4));
+    assert!(!region.contains(0xffc, 4));
+    assert!(!region.contains(usize::MAX, 1));
+}
+
+#[test]
+fn allowed_addresses_are_emulated() {
+    let _serial = serial();
+    let (ram, _) = setup();
+    assert!(is_allowed(ram + 8, 4));
+
+    let mut frame = frame();
+    frame[A2] = ram + 8;
+    frame[A1] = 5;
+    assert!(emulate(
+        encode(Op::Add, W, false, false, A0, A2, A1),
+        &mut frame
+    ));
+    assert_eq!(unsafe { RAM[1] }, 5);
+}
+
+#[test]
+fn other_addresses_fault() {
+    let _serial = serial();
+    let (ram, outside) = setup();
+    let mut frame = frame();
+    let expected = frame;
+
+    for addr in [outside, ram + 12] {
+        frame[A2] = addr;
+        for op in AMOS {
+            assert_eq!(
+                outcome(encode(op, W, false, false, A0, A2, A1), &mut frame),
+                EmulationOutcome::AccessFault {
+                    addr,
+                    access: Access::Store
+                },
+                "{op:?} {addr:#x}"
+            );
+        }
+        #[cfg(feature = "zalrsc")]
+        assert_eq!(
+            outcome(encode(Op::Lr, W, false, false, A0, A2, 0), &mut frame),
+            EmulationOutcome::AccessFault {
+                addr,
+                access: Access::Load
+            }
+        );
+        frame[A2] = expected[A2];
+        assert_eq!(frame, expected);
+    }
+    assert_eq!(unsafe { OUTSIDE[0] }, 7);
+    assert_eq!(unsafe { RAM[1] } >> 32, 0);
+}
+
+#[cfg(target_pointer_width = "64")]
+#[test]
+fn doubles_must_fit_the_region() {
+    let _serial = serial();
+    let (ram, _) = setup();
+    let mut frame = frame();
+
+    // the last allowed word starts a doubleword crossing the end of the region
+    frame[A2] = ram + 8;
+    assert!(matches!(
+        outcome(encode(Op::Swap, D, false, false, A0, A2, A1), &mut frame),
+        EmulationOutcome::AccessFault { .. }
+    ));
+}
+
+/// AMOs on peripheral registers.
+#[cfg(feature = "zaamo")]
+mod mmio {
+    use super::*;
+
+    use riscv_atomic_emulation_trap::region::{is_mmio, set_mmio_regions};
+
+    /// Stands in for a register block.
+    static mut PERIPHERAL: [u32; 2] = [0x10, 0];
+    static mut RAM: [u32; 1] = [0];
+
+    static MMIO: [Region; 1] = [Region::new(
+        addr_of_mut!(PERIPHERAL) as *const u8,
+        (addr_of_mut!(PERIPHERAL) as *const u8).wrapping_add(8),
+    )];
+    static ALLOWED: [Region; 1] = [Region::new(
+        addr_of_mut!(RAM) as *const u8,
+        (addr_of_mut!(RAM) as *const u8).wrapping_add(4),
+    )];
+
+    #[test]
+    fn amo_on_mmio_region() {
+        let _serial = serial();
+        unsafe {
+            set_allowed_regions(&ALLOWED);
+            set_mmio_regions(&MMIO);
+        }
+        let reg = addr_of_mut!(PERIPHERAL) as usize;
+        assert!(is_mmio(reg, 4));
+        assert!(!is_mmio(addr_of_mut!(RAM) as usize, 4));
+        // MMIO regions are allowed even when missing from the allowed regions
+        assert!(is_allowed(reg + 4, 4));
+
+        let mut frame = frame();
+        frame[A2] = reg;
+        frame[A1] = 0x3;
+        assert!(emulate(
+            encode(Op::Or, W, false, true, A0, A2, A1),
+            &mut frame
+        ));
+        assert_eq!(frame[A0], 0x10);
+        assert_eq!(unsafe { PERIPHERAL }, [0x13, 0]);
+    }
+}